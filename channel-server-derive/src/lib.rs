@@ -2,7 +2,10 @@ mod utils;
 
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
-use syn::{parse_macro_input, AttributeArgs, FnArg, ItemFn, Member, Meta, NestedMeta, Result};
+use syn::{
+    parse_macro_input, AttributeArgs, FnArg, ItemFn, Lit, Member, Meta, NestedMeta, Result,
+    ReturnType,
+};
 
 /// Wrap an function as an `Endpoint`.
 ///
@@ -40,26 +43,50 @@ fn generate_handler(internal: bool, input: TokenStream) -> Result<TokenStream> {
         .filter(|attr| attr.path.is_ident("doc"))
         .cloned()
         .collect::<Vec<_>>();
-    let ident = &item_fn.sig.ident;
-    let call_await = if item_fn.sig.asyncness.is_some() {
-        Some(quote::quote!(.await))
+    let description = docs
+        .iter()
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(nv)) => match nv.lit {
+                Lit::Str(doc) => Some(doc.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let description = if description.is_empty() {
+        quote!(None)
     } else {
-        None
+        quote!(Some(#description.to_string()))
+    };
+    let response = match &item_fn.sig.output {
+        ReturnType::Default => "()".to_string(),
+        ReturnType::Type(_, ty) => utils::type_name(ty),
     };
+    let ident = &item_fn.sig.ident;
 
     let mut extractors = Vec::new();
     let mut args = Vec::new();
+    let mut params = Vec::new();
     for (idx, input) in item_fn.sig.inputs.clone().into_iter().enumerate() {
         if let FnArg::Typed(pat) = input {
             let ty = &pat.ty;
             let id = quote::format_ident!("p{}", idx);
             args.push(id.clone());
+            params.push(utils::type_name(ty));
             extractors.push(quote! {
                 let #id = <#ty as #crate_name::FromRequest>::from_request(&req, &mut body)?;
             });
         }
     }
 
+    // async handler 由 runtime::block_on 在工作线程中等待完成
+    let call = if item_fn.sig.asyncness.is_some() {
+        quote!(#crate_name::runtime::block_on(&req, #ident(#(#args),*)))
+    } else {
+        quote!(#ident(#(#args),*))
+    };
+
     let expanded = quote! {
         #(#docs)*
         #[allow(non_camel_case_types)]
@@ -73,9 +100,17 @@ fn generate_handler(internal: bool, input: TokenStream) -> Result<TokenStream> {
                 let (req, mut body) = req.split();
                 #(#extractors)*
                 #item_fn
-                let res = #ident(#(#args),*)#call_await;
+                let res = #call;
                 Ok(res.into_response())
             }
+
+            fn info(&self) -> #crate_name::EndpointInfo {
+                #crate_name::EndpointInfo {
+                    description: #description,
+                    params: vec![#(#params),*],
+                    response: Some(#response),
+                }
+            }
         }
     };
 
@@ -1,7 +1,7 @@
 use proc_macro2::{Span, TokenStream};
 use proc_macro_crate::{crate_name, FoundCrate};
-use quote::quote;
-use syn::Ident;
+use quote::{quote, ToTokens};
+use syn::{Ident, Type};
 
 pub(crate) fn get_crate_name(internal: bool) -> TokenStream {
     if internal {
@@ -15,3 +15,24 @@ pub(crate) fn get_crate_name(internal: bool) -> TokenStream {
         quote!(#name)
     }
 }
+
+/// 类型的名称, 去掉 token 之间多余的空格, 如 `ReqParam < User >` 转换为 `ReqParam<User>`
+pub(crate) fn type_name(ty: &Type) -> String {
+    let tokens = ty.to_token_stream().to_string();
+    let is_ident = |c: Option<char>| matches!(c, Some(c) if c.is_alphanumeric() || c == '_');
+    let chars = tokens.chars().collect::<Vec<_>>();
+    let mut name = String::with_capacity(tokens.len());
+    for (i, c) in chars.iter().enumerate() {
+        if *c == ' ' {
+            let prev = i.checked_sub(1).and_then(|i| chars.get(i)).copied();
+            if !(is_ident(prev) && is_ident(chars.get(i + 1).copied())) {
+                continue;
+            }
+        }
+        name.push(*c);
+        if *c == ',' {
+            name.push(' ');
+        }
+    }
+    name
+}
@@ -1,4 +1,4 @@
-use crate::{Endpoint, ChannelError, Middleware, Request};
+use crate::{Endpoint, EndpointInfo, ChannelError, Middleware, Request, RouteInfo};
 /// Middleware for add any data to request.
 pub struct AddData<T> {
     value: T,
@@ -43,4 +43,12 @@ where
         req.extensions_mut().insert(self.value.clone());
         self.inner.call(req)
     }
+
+    fn info(&self) -> EndpointInfo {
+        self.inner.info()
+    }
+
+    fn paths(&self) -> Vec<RouteInfo> {
+        self.inner.paths()
+    }
 }
@@ -0,0 +1,296 @@
+use std::{
+    fmt::{Debug, Formatter},
+    sync::Arc,
+    time::Duration,
+};
+
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+
+use crate::{
+    handle::{RunState, ServiceHandle},
+    metrics::ServerStats,
+    topic::TopicBus,
+    ChannelClient, ChannelServer, ChannelService, ChannelTopic, Endpoint, Request, Response,
+};
+
+/// 通道的容量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capacity {
+    /// 有界通道, 最多缓存指定数量的消息
+    Bounded(usize),
+    /// 无界通道
+    Unbounded,
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        Self::Bounded(100)
+    }
+}
+
+impl Capacity {
+    pub(crate) fn channel<T>(self) -> (Sender<T>, Receiver<T>) {
+        match self {
+            Capacity::Bounded(cap) => bounded(cap),
+            Capacity::Unbounded => unbounded(),
+        }
+    }
+}
+
+/// 请求队列满时, client 发起请求的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// 阻塞, 直到队列有空位
+    #[default]
+    Block,
+    /// 立即返回 [`ChannelError::QueueFull`](crate::ChannelError::QueueFull)
+    Reject,
+}
+
+/// 响应队列满时, server 发回响应的处理方式
+///
+/// 响应被丢弃时, client 中对应请求的状态会变为 [`ChannelError::ResponseDropped`](crate::ChannelError::ResponseDropped)
+#[derive(Clone, Default)]
+pub enum ResponsePolicy {
+    /// 阻塞工作线程, 直到 client 取走响应, 最多等待指定的时间, 超时后丢弃
+    ///
+    /// 所有 client 共用工作线程, 不取响应的 client 会让其它 client 的请求也跟着等待, 时间不宜过长.
+    /// 和 [`OverflowPolicy::Block`] 一起用在同时负责 run_once 的线程上时,
+    /// 发起的请求超过队列容量后 client 和工作线程会互相等待, 直到超时丢弃响应
+    Block(Duration),
+    /// 丢弃队列中最早的响应, 为新的响应腾出空位
+    #[default]
+    DropOldest,
+    /// 把放不下的响应交给回调处理
+    DeadLetter(Arc<dyn Fn(Response) + Send + Sync>),
+}
+
+impl ResponsePolicy {
+    pub fn dead_letter(hook: impl Fn(Response) + Send + Sync + 'static) -> Self {
+        Self::DeadLetter(Arc::new(hook))
+    }
+}
+
+impl Debug for ResponsePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Block(timeout) => f.debug_tuple("Block").field(timeout).finish(),
+            Self::DropOldest => f.write_str("DropOldest"),
+            Self::DeadLetter(_) => f.write_str("DeadLetter"),
+        }
+    }
+}
+
+/// 订阅的主题数据在 client 中的缓存方式
+///
+/// 数据在 [`ChannelClient::fetch_topic`](crate::ChannelClient::fetch_topic) 时取出, 取出之前按照该策略限制数量.
+/// 数量 n 为 0 时按 1 处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicPolicy {
+    /// 只保留最新的 n 条数据, 丢弃最早的
+    KeepLatest(usize),
+    /// 只保留最新的一条数据, 适用于视频帧, 传感器读数等只关心当前值的主题
+    Latest,
+    /// 已有 n 条数据时丢弃新的数据
+    DropNewest(usize),
+    /// 已有 n 条数据时阻塞发布者, 直到 client 取走数据或者取消订阅
+    Block(usize),
+    /// 不限制数量
+    Unbounded,
+}
+
+impl Default for TopicPolicy {
+    fn default() -> Self {
+        Self::DropNewest(100)
+    }
+}
+
+/// 工作线程的配置
+#[derive(Debug, Clone)]
+pub(crate) struct WorkerConfig {
+    pub(crate) workers: usize,
+    pub(crate) thread_name: String,
+    pub(crate) stack_size: Option<usize>,
+    /// 执行 async handler 的 runtime, None 时由服务创建
+    #[cfg(feature = "tokio")]
+    pub(crate) runtime: Option<tokio::runtime::Handle>,
+}
+
+/// 用于配置并启动 [`ChannelService`](crate::ChannelService)
+///
+/// ```ignore
+/// let (client, topic, handle) = ChannelService::builder()
+///     .request_capacity(Capacity::Unbounded)
+///     .workers(8)
+///     .thread_name("motor")
+///     .start(ep);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChannelServiceBuilder {
+    request_capacity: Capacity,
+    response_capacity: Capacity,
+    topic_policy: TopicPolicy,
+    overflow: OverflowPolicy,
+    response_policy: ResponsePolicy,
+    worker: WorkerConfig,
+}
+
+impl Default for ChannelServiceBuilder {
+    fn default() -> Self {
+        Self {
+            request_capacity: Capacity::default(),
+            response_capacity: Capacity::default(),
+            topic_policy: TopicPolicy::default(),
+            overflow: OverflowPolicy::default(),
+            response_policy: ResponsePolicy::default(),
+            worker: WorkerConfig {
+                workers: default_workers(),
+                thread_name: "channel-server-worker".into(),
+                stack_size: None,
+                #[cfg(feature = "tokio")]
+                runtime: None,
+            },
+        }
+    }
+}
+
+impl ChannelServiceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 请求队列的容量
+    #[must_use]
+    pub fn request_capacity(mut self, capacity: Capacity) -> Self {
+        self.request_capacity = capacity;
+        self
+    }
+
+    /// 响应队列的容量
+    #[must_use]
+    pub fn response_capacity(mut self, capacity: Capacity) -> Self {
+        self.response_capacity = capacity;
+        self
+    }
+
+    /// 每个订阅的主题队列容量, 队列满时丢弃发给该订阅的新数据
+    ///
+    /// 等同于 `topic_policy(TopicPolicy::DropNewest(n))`, 无界时为 `TopicPolicy::Unbounded`
+    #[must_use]
+    pub fn topic_capacity(mut self, capacity: Capacity) -> Self {
+        self.topic_policy = match capacity {
+            Capacity::Bounded(n) => TopicPolicy::DropNewest(n),
+            Capacity::Unbounded => TopicPolicy::Unbounded,
+        };
+        self
+    }
+
+    /// 订阅主题时默认的 TopicPolicy, 可以在订阅时通过
+    /// [`ChannelClient::subject_with`](crate::ChannelClient::subject_with) 单独指定
+    #[must_use]
+    pub fn topic_policy(mut self, policy: TopicPolicy) -> Self {
+        self.topic_policy = policy;
+        self
+    }
+
+    /// 请求队列满时的处理方式
+    #[must_use]
+    pub fn overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    /// 响应队列满时的处理方式
+    #[must_use]
+    pub fn response_policy(mut self, policy: ResponsePolicy) -> Self {
+        self.response_policy = policy;
+        self
+    }
+
+    /// 工作线程数量, 最少 1 个
+    #[must_use]
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.worker.workers = workers.max(1);
+        self
+    }
+
+    /// 工作线程名称的前缀, 线程名称为 `{prefix}-{index}`
+    #[must_use]
+    pub fn thread_name(mut self, prefix: impl Into<String>) -> Self {
+        self.worker.thread_name = prefix.into();
+        self
+    }
+
+    /// 工作线程的栈大小, 单位: 字节
+    #[must_use]
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.worker.stack_size = Some(size);
+        self
+    }
+
+    /// 使用已有的 tokio runtime 执行 async handler, 默认由服务创建一个
+    ///
+    /// 工作线程通过 `Handle::block_on` 等待 handler, current_thread runtime 不会在其它线程驱动 io 和定时器,
+    /// 所以必须是多线程的 runtime
+    ///
+    /// # Panics
+    ///
+    /// handle 属于 current_thread runtime 时 panic
+    #[cfg(feature = "tokio")]
+    #[must_use]
+    pub fn runtime(mut self, handle: tokio::runtime::Handle) -> Self {
+        assert!(
+            handle.runtime_flavor() != tokio::runtime::RuntimeFlavor::CurrentThread,
+            "channel-server requires a multi-thread tokio runtime"
+        );
+        self.worker.runtime = Some(handle);
+        self
+    }
+
+    /// 启动服务
+    pub fn start(
+        self,
+        ep: impl Endpoint + 'static + Clone,
+    ) -> (ChannelClient, ChannelTopic, ServiceHandle) {
+        let (req_tx, req_rx) = self.request_capacity.channel::<Request>();
+        let topics = Arc::new(TopicBus::new(self.topic_policy));
+        let (shutdown_tx, shutdown_rx) = unbounded::<()>();
+        let (done_tx, done_rx) = bounded::<()>(0);
+        let stats = Arc::new(ServerStats::new(self.worker.workers));
+        let running = Arc::new(RunState::new());
+        let service = ChannelService::new(
+            req_tx,
+            topics.clone(),
+            self.response_capacity,
+            self.response_policy,
+            self.overflow,
+            stats.clone(),
+            running,
+        );
+        let client = service.new_client();
+        let topic = ChannelTopic::new(topics);
+        let server = ChannelServer::new(
+            req_rx,
+            topic.clone(),
+            stats,
+            (shutdown_tx.clone(), shutdown_rx),
+            done_tx,
+            #[cfg(feature = "tokio")]
+            crate::runtime::AsyncRuntime::new(
+                self.worker.runtime.clone(),
+                &self.worker.thread_name,
+            ),
+        );
+        let workers = server.run(ep, &self.worker);
+        let handle = ServiceHandle::new(service, shutdown_tx, done_rx, workers);
+        (client, topic, handle)
+    }
+}
+
+/// 默认的工作线程数量, handler 中经常有阻塞的 io 操作, 所以至少 4 个
+fn default_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .max(4)
+}
@@ -15,11 +15,11 @@ impl<T> RcCell<T> {
             cell: Rc::new(RefCell::new(data)),
         }
     }
-    pub fn as_mut(&self) -> RefMut<T> {
+    pub fn as_mut(&self) -> RefMut<'_, T> {
         self.cell.as_ref().borrow_mut()
     }
 
-    pub fn as_ref(&self) -> Ref<T> {
+    pub fn as_ref(&self) -> Ref<'_, T> {
         self.cell.as_ref().borrow()
     }
 }
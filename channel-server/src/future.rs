@@ -0,0 +1,122 @@
+//! 以 Future 的方式等待请求的结果
+//!
+//! [`ChannelClient::send`](crate::ChannelClient::send) 返回的 [`ResponseFuture`] 在响应到达时通过 [`Waker`] 唤醒,
+//! 不依赖特定的 runtime, 可以在任意执行器中 await.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::{request::cancel::CancelToken, ChannelError, RequestId, Response};
+
+/// 请求的结果, 只在请求结束时完成, handler 报告的进度会被忽略. 完成前 drop 时取消请求
+///
+/// ```ignore
+/// let res = client.send(Request::with_param("/version".into(), Param::empty())).await?;
+/// ```
+#[must_use = "future 被 drop 时会取消请求"]
+pub struct ResponseFuture {
+    id: RequestId,
+    state: Arc<Mutex<State>>,
+    cancel: CancelToken,
+}
+
+#[derive(Default)]
+struct State {
+    result: Option<Result<Response, ChannelError>>,
+    /// 结果已经被取走
+    done: bool,
+    waker: Option<Waker>,
+}
+
+impl ResponseFuture {
+    /// 返回 future 以及交给 server 的 ResponseSlot
+    pub(crate) fn new(id: RequestId, cancel: CancelToken) -> (Self, ResponseSlot) {
+        let state = Arc::new(Mutex::new(State::default()));
+        let slot = ResponseSlot {
+            state: Some(state.clone()),
+        };
+        (Self { id, state, cancel }, slot)
+    }
+
+    /// 请求发送失败时, 直接以错误结束的 future
+    pub(crate) fn failed(id: RequestId, e: ChannelError) -> Self {
+        let (fut, mut slot) = Self::new(id, CancelToken::new());
+        slot.finish(Err(e));
+        fut
+    }
+
+    /// 请求的 id
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    /// 取消请求, 之后 future 以 [`StatusCode::Cancelled`](crate::StatusCode::Cancelled) 的响应完成
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Future for ResponseFuture {
+    type Output = Result<Response, ChannelError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(result) = state.result.take() {
+            state.done = true;
+            return Poll::Ready(result);
+        }
+        if state.done {
+            return Poll::Ready(Err(ChannelError::ResRecvError));
+        }
+        match &mut state.waker {
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => state.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+/// 不再等待结果时取消请求, 避免 handler 继续执行
+impl Drop for ResponseFuture {
+    fn drop(&mut self) {
+        if !self.state.lock().unwrap().done {
+            self.cancel.cancel();
+        }
+    }
+}
+
+/// server 通过它完成对应的 ResponseFuture
+///
+/// 请求没有执行就被丢弃时 (例如服务停止), future 以 [`ChannelError::ServiceStopped`] 完成
+pub(crate) struct ResponseSlot {
+    state: Option<Arc<Mutex<State>>>,
+}
+
+impl ResponseSlot {
+    pub(crate) fn send(mut self, res: Response) {
+        self.finish(Ok(res));
+    }
+
+    fn finish(&mut self, result: Result<Response, ChannelError>) {
+        if let Some(state) = self.state.take() {
+            let waker = {
+                let mut state = state.lock().unwrap();
+                state.result = Some(result);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for ResponseSlot {
+    fn drop(&mut self) {
+        self.finish(Err(ChannelError::ServiceStopped));
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Sender};
+
+use crate::{ChannelClient, ChannelError, ChannelService};
+
+/// 服务的运行状态, 停止时唤醒所有为 client 阻塞的工作线程
+pub(crate) struct RunState {
+    running: AtomicBool,
+    /// stop 时 drop, stop_rx 随之断开
+    stop_tx: Mutex<Option<Sender<()>>>,
+    stop_rx: Receiver<()>,
+}
+
+impl RunState {
+    pub(crate) fn new() -> Self {
+        let (stop_tx, stop_rx) = bounded(0);
+        Self {
+            running: AtomicBool::new(true),
+            stop_tx: Mutex::new(Some(stop_tx)),
+            stop_rx,
+        }
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// 服务停止后可以立即接收 (断开), 用于在 select! 中放弃阻塞的操作
+    pub(crate) fn stopped(&self) -> &Receiver<()> {
+        &self.stop_rx
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+        self.stop_tx.lock().unwrap().take();
+    }
+}
+
+/// 用于停止 [`ChannelService`]
+///
+/// drop 时不会停止服务, 工作线程会一直运行到所有 client 和 ChannelService 都被 drop
+pub struct ServiceHandle {
+    service: ChannelService,
+    /// shutdown 时给每个工作线程发送一个停止信号
+    shutdown_tx: Sender<()>,
+    /// 所有工作线程退出后断开
+    done_rx: Receiver<()>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ServiceHandle {
+    pub(crate) fn new(
+        service: ChannelService,
+        shutdown_tx: Sender<()>,
+        done_rx: Receiver<()>,
+        workers: Vec<JoinHandle<()>>,
+    ) -> Self {
+        Self {
+            service,
+            shutdown_tx,
+            done_rx,
+            workers,
+        }
+    }
+
+    /// 服务是否还在接受请求
+    pub fn is_running(&self) -> bool {
+        self.service.is_running()
+    }
+
+    /// 用于在其它线程创建新的 client
+    pub fn service(&self) -> ChannelService {
+        self.service.clone()
+    }
+
+    /// 创建一个新的 client, 见 [`ChannelService::new_client`]
+    pub fn new_client(&self) -> ChannelClient {
+        self.service.new_client()
+    }
+
+    /// 停止服务
+    ///
+    /// 不再接受新的请求, 工作线程处理完正在执行和队列中的请求后退出.
+    /// 响应队列或者主题队列已满时, 不再为 client 等待 ([`ResponsePolicy::Block`](crate::builder::ResponsePolicy::Block),
+    /// [`TopicPolicy::Block`](crate::builder::TopicPolicy::Block)), 数据被丢弃.
+    /// 超过 timeout 仍有工作线程未退出时返回 [`ChannelError::Timeout`], 这些线程不再等待
+    pub fn shutdown(mut self, timeout: Duration) -> Result<(), ChannelError> {
+        let deadline = Instant::now() + timeout;
+        // 为不取数据的 client 阻塞的工作线程不再等待, 数据被丢弃
+        self.service.running.stop();
+        self.service.topics.stop();
+        for _ in &self.workers {
+            self.shutdown_tx.send(()).ok();
+        }
+
+        match self.done_rx.recv_deadline(deadline) {
+            Err(RecvTimeoutError::Disconnected) => {
+                for worker in self.workers.drain(..) {
+                    worker.join().ok();
+                }
+                Ok(())
+            }
+            Ok(()) | Err(RecvTimeoutError::Timeout) => Err(ChannelError::Timeout),
+        }
+    }
+}
@@ -1,25 +1,51 @@
 use add_data::{AddData, AddDataEndpoint};
-use ahash::AHashMap;
+use builder::{
+    Capacity, ChannelServiceBuilder, OverflowPolicy, ResponsePolicy, TopicPolicy, WorkerConfig,
+};
 use bytes::Bytes;
-use crossbeam::channel::{bounded, Receiver, Sender};
+use crossbeam::channel::{select, unbounded, Receiver, SendTimeoutError, Sender, TrySendError};
 use extensions::Extensions;
-use serde::Serialize;
+use handle::{RunState, ServiceHandle};
+use metrics::{Metrics, ServerStats};
+use reply::{Reply, ReplySender};
+use request::{cancel::CancelToken, progress::Progress};
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
+    any::Any,
     collections::HashMap,
     fmt::{Debug, Formatter},
     ops::Deref,
-    sync::{Arc, RwLock},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
+use topic::{TopicBus, TopicQueue};
 
 pub mod add_data;
+pub mod builder;
 pub mod common;
 pub mod extensions;
+pub mod future;
+pub mod handle;
+pub mod metrics;
 pub mod request;
 pub mod response;
+pub mod route;
+pub mod runtime;
+pub mod timeout;
+pub mod topic;
 
 pub mod prelude;
+mod reply;
 
 pub use channel_server_derive::handler;
+pub use future::ResponseFuture;
+pub use route::{Route, RouteInfo};
+pub use topic::{ChannelTopic, Subscription, Topic};
 
 #[derive(Default, Clone)]
 pub struct Body(Option<Bytes>);
@@ -71,7 +97,33 @@ impl Deref for Body {
     }
 }
 
+/// 请求的唯一标识, 用于将 Response 与 Request 对应起来
+///
+/// 同一个 uri 可以同时有多个请求在执行, 它们通过 id 区分
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    /// 生成一个新的 id, 0 保留给 topic 等没有对应请求的 Response
+    pub fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    #[inline]
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 pub struct Request {
+    id: RequestId,
     uri: String,
     /// 用于传递参数， json序列化字符串
     param: Param,
@@ -79,15 +131,19 @@ pub struct Request {
     body: Body,
     /// 主要是 Middleware 使用的
     extensions: Extensions,
+    /// 响应发回的地方
+    reply: Option<Reply>,
 }
 
 impl Request {
     pub fn new(uri: impl Into<String>, param: Param, body: Body) -> Request {
         Self {
+            id: RequestId::next(),
             uri: uri.into(),
             param,
             body,
             extensions: Extensions::new(),
+            reply: None,
         }
     }
 
@@ -105,11 +161,33 @@ impl Request {
         (self, body)
     }
 
+    #[inline]
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
     #[inline]
     pub fn uri_ref(&self) -> &str {
         &self.uri
     }
 
+    pub(crate) fn set_uri(&mut self, uri: String) {
+        self.uri = uri;
+    }
+
+    pub(crate) fn set_reply(&mut self, reply: Reply) {
+        self.reply = Some(reply);
+    }
+
+    pub(crate) fn take_reply(&mut self) -> Option<Reply> {
+        self.reply.take()
+    }
+
+    /// 放入请求队列失败时取回 reply, 错误直接返回给调用者
+    fn discard_reply(mut self) {
+        self.reply.take();
+    }
+
     /// Returns a reference to the associated extensions.
     #[inline]
     pub fn extensions(&self) -> &Extensions {
@@ -130,7 +208,20 @@ impl Request {
 
 impl Debug for Request {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Request").field("uri", &self.uri).finish()
+        f.debug_struct("Request")
+            .field("id", &self.id)
+            .field("uri", &self.uri)
+            .finish()
+    }
+}
+
+/// 没有被 server 处理就被丢弃的请求 (例如服务停止时还在请求队列中), 通知发起请求的 client,
+/// 否则 client 中的请求状态会一直停在 Ready. ResponseFuture 由 ResponseSlot 的 drop 通知
+impl Drop for Request {
+    fn drop(&mut self) {
+        if let Some(Reply::Client(reply)) = &self.reply {
+            reply.discard(self.id);
+        }
     }
 }
 
@@ -146,6 +237,8 @@ pub enum StatusCode {
     Pending(String),
     /// 还未开始
     NotStart(String),
+    /// 已取消
+    Cancelled(String),
 }
 
 impl Default for StatusCode {
@@ -172,32 +265,59 @@ impl StatusCode {
         Self::NotStart("未执行".into())
     }
 
+    pub fn cancelled() -> Self {
+        Self::Cancelled("已取消".into())
+    }
+
     pub fn is_ok(&self) -> bool {
         matches!(self, StatusCode::Ok(_))
     }
+
+    pub fn is_pending(&self) -> bool {
+        matches!(self, StatusCode::Pending(_))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, StatusCode::Cancelled(_))
+    }
+
+    /// 请求是否已经结束, 即执行成功, 失败或者被取消
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            StatusCode::Ok(_) | StatusCode::Fail(_) | StatusCode::Cancelled(_)
+        )
+    }
 }
 
 #[derive(Default, Clone)]
 pub struct Response {
+    id: RequestId,
     uri: String,
     status: StatusCode,
     body: Body,
+    /// 执行进度, 0.0 ~ 1.0, 只有 Pending 的响应才有
+    progress: Option<f32>,
 }
 
 impl Response {
     pub fn new() -> Self {
         Self {
+            id: RequestId::default(),
             uri: String::new(),
             status: StatusCode::ready(),
             body: Body(None),
+            progress: None,
         }
     }
 
     pub fn topic(uri: &str) -> Self {
         Self {
+            id: RequestId::default(),
             uri: uri.into(),
             status: StatusCode::ok(),
             body: Body(None),
+            progress: None,
         }
     }
 
@@ -215,11 +335,30 @@ impl Response {
         &self.status
     }
 
+    pub fn progress(mut self, progress: f32) -> Self {
+        self.progress = Some(progress.clamp(0.0, 1.0));
+        self
+    }
+
+    /// 执行进度, 见 [`Progress`](crate::request::progress::Progress)
+    pub fn progress_ref(&self) -> Option<f32> {
+        self.progress
+    }
+
     pub fn uri(mut self, uri: String) -> Response {
         self.uri = uri;
         self
     }
 
+    pub fn id(mut self, id: RequestId) -> Response {
+        self.id = id;
+        self
+    }
+
+    pub fn id_ref(&self) -> RequestId {
+        self.id
+    }
+
     pub fn uri_ref(&self) -> &str {
         &self.uri
     }
@@ -242,8 +381,10 @@ impl Debug for Response {
             0
         };
         f.debug_struct("Response")
+            .field("id", &self.id)
             .field("uri", &self.uri)
             .field("status", &self.status)
+            .field("progress", &self.progress)
             .field("body length", &len)
             .finish()
     }
@@ -261,21 +402,63 @@ pub trait Endpoint: Send + Sync {
     fn call(&self, req: Request) -> Result<Self::Output, ChannelError>;
 
     fn get_response(&self, req: Request) -> Response {
+        let id = req.id();
         let uri = req.uri_ref().to_string();
         let res = self
             .call(req)
             .map(IntoResponse::into_response)
             .unwrap_or_else(|err| err.into_response());
-        res.uri(uri)
+        res.uri(uri).id(id)
+    }
+
+    /// 描述信息, `#[handler]` 会根据函数的文档和签名生成
+    fn info(&self) -> EndpointInfo {
+        EndpointInfo::default()
     }
+
+    /// 内部注册的路径, 只有 Route 需要实现, 用于列出 nest 挂载的路径
+    fn paths(&self) -> Vec<RouteInfo> {
+        Vec::new()
+    }
+}
+
+/// Endpoint 的描述信息, 用于 [`Route::paths`]
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct EndpointInfo {
+    /// 文档注释
+    pub description: Option<String>,
+    /// 参数 (extractor) 的类型名称
+    pub params: Vec<&'static str>,
+    /// 返回值的类型名称
+    pub response: Option<&'static str>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ChannelError {
-    #[error("请求已经在队列中")]
-    ReqExistInQueue,
     #[error("请求发送失败")]
     ReqSendError,
+    #[error("请求队列已满")]
+    QueueFull,
+    #[error("服务已停止")]
+    ServiceStopped,
+    #[error("响应接收失败")]
+    ResRecvError,
+
+    /// 等待响应超时.
+    #[error("请求超时")]
+    Timeout,
+
+    /// handler 检查到请求被取消后返回, 响应的状态为 [`StatusCode::Cancelled`].
+    #[error("已取消")]
+    Cancelled,
+
+    /// 超时后仍未结束的 handler 达到 [`Timeout`](timeout::Timeout) 的上限.
+    #[error("超时后仍在执行的 handler 过多")]
+    TooManyTimedOut,
+
+    /// 响应队列已满, 响应被 server 丢弃.
+    #[error("响应被丢弃")]
+    ResponseDropped,
 
     /// Io error.
     #[error("io: {0}")]
@@ -284,6 +467,9 @@ pub enum ChannelError {
     #[error("解析json异常")]
     ParseJsonError,
 
+    #[error("序列化json异常: {0}")]
+    SerializeJsonError(String),
+
     /// Body has been taken by other extractors.
     #[error("the request body has no data")]
     BodyNoData,
@@ -298,6 +484,17 @@ pub enum ChannelError {
     #[error("路径未找到: {0}")]
     PathNotFoundError(String),
 
+    #[error("路径无效: {0}")]
+    InvalidPath(String),
+
+    /// 路径参数与 Path 的类型不匹配.
+    #[error("路径参数解析失败: {0}")]
+    PathParamError(String),
+
+    /// handler 执行时 panic.
+    #[error("handler 异常: {0}")]
+    HandlerPanicked(String),
+
     /// 获取Data异常.
     #[error("Get data 异常: {0}")]
     GetDataError(String),
@@ -308,7 +505,11 @@ pub enum ChannelError {
 
 impl IntoResponse for ChannelError {
     fn into_response(self) -> Response {
-        Response::new().status(StatusCode::Fail(self.to_string()))
+        let status = match self {
+            ChannelError::Cancelled => StatusCode::cancelled(),
+            e => StatusCode::Fail(e.to_string()),
+        };
+        Response::new().status(status)
     }
 }
 
@@ -374,94 +575,168 @@ pub trait Middleware<E: Endpoint> {
 // }
 
 #[derive(Clone)]
-pub struct Route {
-    map: Arc<RwLock<AHashMap<&'static str, BoxEndpoint<'static>>>>,
+struct ChannelServer {
+    res_rx: Receiver<Request>,
+    /// 注入到每个请求中, 供 handler 发布主题数据
+    topic: ChannelTopic,
+    stats: Arc<ServerStats>,
+    /// 收到信号时工作线程退出
+    shutdown_rx: Receiver<()>,
+    /// 工作线程自己持有一个, ServiceHandle 被 drop 时 shutdown_rx 不会断开
+    _shutdown_tx: Sender<()>,
+    /// 工作线程退出时 drop
+    done_tx: Sender<()>,
+    /// 执行 async handler 的 runtime
+    #[cfg(feature = "tokio")]
+    runtime: Arc<runtime::AsyncRuntime>,
 }
 
-impl Route {
-    pub fn new() -> Self {
+impl ChannelServer {
+    pub(crate) fn new(
+        req_rx: Receiver<Request>,
+        topic: ChannelTopic,
+        stats: Arc<ServerStats>,
+        (shutdown_tx, shutdown_rx): (Sender<()>, Receiver<()>),
+        done_tx: Sender<()>,
+        #[cfg(feature = "tokio")] runtime: runtime::AsyncRuntime,
+    ) -> ChannelServer {
         Self {
-            map: Arc::default(),
+            res_rx: req_rx,
+            topic,
+            stats,
+            shutdown_rx,
+            _shutdown_tx: shutdown_tx,
+            done_tx,
+            #[cfg(feature = "tokio")]
+            runtime: Arc::new(runtime),
         }
     }
-}
-
-impl Endpoint for Route {
-    type Output = Response;
 
-    fn call(&self, req: Request) -> Result<Self::Output, ChannelError> {
-        let map = self.map.read().unwrap();
-        if map.contains_key(req.uri_ref()) {
-            let ep = &map[req.uri_ref()];
-            ep.call(req)
-        } else {
-            Err(ChannelError::PathNotFoundError(req.uri_ref().into()))
-        }
+    /// 启动工作线程, 共同从请求队列中取出请求处理
+    pub(crate) fn run(
+        self,
+        ep: impl Endpoint + 'static + Clone,
+        config: &WorkerConfig,
+    ) -> Vec<JoinHandle<()>> {
+        (0..config.workers)
+            .map(|i| {
+                let ep = ep.clone();
+                let server = self.clone();
+                let mut builder =
+                    std::thread::Builder::new().name(format!("{}-{}", config.thread_name, i));
+                if let Some(size) = config.stack_size {
+                    builder = builder.stack_size(size);
+                }
+                builder
+                    .spawn(move || server.work(ep))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect()
     }
-}
 
-impl Route {
-    #[must_use]
-    pub fn at(self, path: &'static str, ep: impl Endpoint<Output = Response> + 'static) -> Self {
-        {
-            let mut map = self.map.write().unwrap();
-            if map.contains_key(path) {
-                panic!("duplicate path: {}", path);
+    fn work(self, ep: impl Endpoint) {
+        loop {
+            select! {
+                recv(self.res_rx) -> req => match req {
+                    Ok(req) => self.handle(&ep, req),
+                    Err(_) => break,
+                },
+                recv(self.shutdown_rx) -> _ => break,
             }
-            map.insert(path, ep.boxed());
         }
-        self
-    }
-}
-
-struct ChannelServer {
-    res_rx: Receiver<Request>,
-    req_tx: Sender<Response>,
-}
-
-impl ChannelServer {
-    pub(crate) fn new(req_rx: Receiver<Request>, res_tx: Sender<Response>) -> ChannelServer {
-        Self {
-            res_rx: req_rx,
-            req_tx: res_tx,
+        // 停止前处理完队列中剩余的请求
+        while let Ok(req) = self.res_rx.try_recv() {
+            self.handle(&ep, req);
         }
+        // 通知 ServiceHandle 该工作线程已经退出
+        drop(self.done_tx);
     }
 
-    pub fn run(self, ep: impl Endpoint + 'static + Clone) {
-        std::thread::spawn(move || {
-            while let Ok(req) = self.res_rx.recv() {
-                let ep = ep.clone();
-                let req_tx = self.req_tx.clone();
-                std::thread::spawn(move || {
-                    let res = ep.get_response(req);
-                    req_tx.try_send(res).ok();
-                });
+    fn handle(&self, ep: &impl Endpoint, mut req: Request) {
+        let reply = req.take_reply();
+        let id = req.id();
+        let uri = req.uri_ref().to_string();
+        req.extensions_mut().insert(self.topic.clone());
+        #[cfg(feature = "tokio")]
+        req.extensions_mut().insert(self.runtime.handle().clone());
+        // 不是 client 发起的请求没有 CancelToken, 补上一个, 保证 handler 可以提取
+        let cancel = match req.extensions().get::<CancelToken>() {
+            Some(cancel) => cancel.clone(),
+            None => {
+                let cancel = CancelToken::new();
+                req.extensions_mut().insert(cancel.clone());
+                cancel
             }
+        };
+        if let Some(reply) = &reply {
+            // ResponseFuture 只关心最终的结果, 不需要进度
+            let progress_reply = match reply {
+                Reply::Client(reply) => Some(reply.clone()),
+                Reply::Future(_) => None,
+            };
+            let progress = Progress::new(id, uri.clone(), progress_reply);
+            req.extensions_mut().insert(progress);
+        }
+        self.stats.begin();
+        // 在队列中等待时已经取消的请求不再执行
+        let res = (!cancel.is_cancelled()).then(|| {
+            // handler 异常不能让工作线程退出, 转换为失败的响应
+            panic::catch_unwind(AssertUnwindSafe(|| ep.get_response(req))).unwrap_or_else(
+                |payload| {
+                    let msg = panic_message(payload.as_ref());
+                    log::error!("handler panicked: {}: {}", uri, msg);
+                    ChannelError::HandlerPanicked(msg)
+                        .into_response()
+                        .uri(uri.clone())
+                        .id(id)
+                },
+            )
         });
+        // handler 已经执行完时保留它的结果, 即使期间被取消, 操作可能已经完成了
+        let res = match res {
+            Some(res) => res,
+            None => Response::new()
+                .status(StatusCode::cancelled())
+                .uri(uri)
+                .id(id),
+        };
+        // 响应发出后才算处理完成, 按照 ResponsePolicy 阻塞的时间也算在内
+        match reply {
+            Some(reply) => reply.send(res),
+            None => log::warn!("response without client: {:?}", &res),
+        }
+        self.stats.end();
+    }
+}
+
+/// 取出 panic 时的消息, panic!() 的参数通常是 &str 或者 String
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".into()
     }
 }
 
 pub struct ChannelClient {
     req_tx: Sender<Request>,
+    /// 随请求一起发给 server, server 通过它把响应发回这个 client
+    reply: ReplySender,
     res_rx: Receiver<Response>,
+    dropped_rx: Receiver<(RequestId, ChannelError)>,
     res_queue: Vec<Response>,
-    topic_rx: Receiver<Response>,
-    topic_queue: HashMap<&'static str, Vec<Response>>,
-}
-
-#[derive(Clone)]
-pub struct ChannelTopic {
-    topic_tx: Sender<Response>,
-}
-
-impl ChannelTopic {
-    pub fn new(topic_tx: Sender<Response>) -> Self {
-        Self { topic_tx }
-    }
-    pub fn publish(&self, res: Response) {
-        // 发送成功还是失败并不重要
-        self.topic_tx.send(res).ok();
-    }
+    /// 还未结束的请求的取消标记
+    cancel_tokens: HashMap<RequestId, CancelToken>,
+    /// 在 TopicBus 中注册的订阅者 id
+    topic_id: u64,
+    /// 订阅的主题 (可能包含通配符) 和收到的数据
+    topic_queue: HashMap<String, Arc<TopicQueue>>,
+    topics: Arc<TopicBus>,
+    overflow: OverflowPolicy,
+    stats: Arc<ServerStats>,
+    running: Arc<RunState>,
 }
 
 impl ChannelClient {
@@ -469,7 +744,7 @@ impl ChannelClient {
         &mut self,
         uri: impl Into<String>,
         param: Param,
-    ) -> Result<(), ChannelError> {
+    ) -> Result<RequestId, ChannelError> {
         let req = Request::new(uri.into(), param, Body::empty());
         self.req(req)
     }
@@ -478,7 +753,7 @@ impl ChannelClient {
         &mut self,
         uri: impl Into<String>,
         body: Body,
-    ) -> Result<(), ChannelError> {
+    ) -> Result<RequestId, ChannelError> {
         let req = Request::new(uri.into(), Param::empty(), body);
         self.req(req)
     }
@@ -488,30 +763,182 @@ impl ChannelClient {
         uri: impl Into<String>,
         param: Param,
         body: Body,
-    ) -> Result<(), ChannelError> {
+    ) -> Result<RequestId, ChannelError> {
         let req = Request::new(uri.into(), param, body);
         self.req(req)
     }
 
-    /// 发起请求
-    pub fn req(&mut self, req: Request) -> Result<(), ChannelError> {
-        // 先检查队列中是否有这个请求
-        let item = self
-            .res_queue
-            .iter()
-            .find(|res| res.uri_ref() == req.uri_ref());
-        if item.is_some() {
-            return Err(ChannelError::ReqExistInQueue);
+    /// 发起请求, 返回请求的 id, 之后通过 id 查询结果
+    pub fn req(&mut self, mut req: Request) -> Result<RequestId, ChannelError> {
+        if !self.running.is_running() {
+            return Err(ChannelError::ServiceStopped);
         }
+        let id = req.id();
+        let cancel = CancelToken::new();
+        req.extensions_mut().insert(cancel.clone());
+        self.cancel_tokens.insert(id, cancel);
 
         // 添加 请求状态
         self.res_queue
-            .push(Response::new().uri(req.uri_ref().into()));
+            .push(Response::new().uri(req.uri_ref().into()).id(id));
 
         // 发送请求
-        self.req_tx
-            .send(req)
-            .map_err(|_e| ChannelError::ReqSendError)
+        if let Err(e) = self.enqueue(req) {
+            self.clean_by_id(id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// 取消请求, 还未执行的请求状态变为 [`StatusCode::Cancelled`]
+    ///
+    /// 正在执行的 handler 可以通过 [`CancelToken`] 得知, 返回 [`ChannelError::Cancelled`] 时状态同样为已取消,
+    /// 正常返回时保留 handler 的结果
+    ///
+    /// 返回 false 表示请求已经结束或者不存在
+    pub fn cancel(&mut self, id: RequestId) -> bool {
+        match self.cancel_tokens.get(&id) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 发起请求, 返回的 future 在请求结束时完成, 不需要调用 run_once
+    ///
+    /// 通过 [`Waker`](std::task::Waker) 唤醒, 不依赖特定的 runtime. 结果不会放入响应队列,
+    /// 也不能通过 [`ChannelClient::cancel`] 取消, 需要使用 [`ResponseFuture::cancel`].
+    /// 不论 OverflowPolicy, 都不会阻塞调用者的执行器, 请求队列满时 future 以 [`ChannelError::QueueFull`] 完成.
+    /// future 在完成前被 drop 时取消请求
+    pub fn send(&self, mut req: Request) -> ResponseFuture {
+        let id = req.id();
+        if !self.running.is_running() {
+            return ResponseFuture::failed(id, ChannelError::ServiceStopped);
+        }
+        let cancel = CancelToken::new();
+        req.extensions_mut().insert(cancel.clone());
+        let (fut, slot) = ResponseFuture::new(id, cancel);
+        req.set_reply(Reply::Future(slot));
+        match self.req_tx.try_send(req) {
+            Ok(()) => fut,
+            Err(TrySendError::Full(_)) => ResponseFuture::failed(id, ChannelError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => {
+                ResponseFuture::failed(id, ChannelError::ReqSendError)
+            }
+        }
+    }
+
+    /// 按照 OverflowPolicy 把请求放入请求队列
+    fn enqueue(&self, mut req: Request) -> Result<(), ChannelError> {
+        req.set_reply(Reply::Client(self.reply.clone()));
+        match self.overflow {
+            OverflowPolicy::Block => self.req_tx.send(req).map_err(|e| {
+                e.into_inner().discard_reply();
+                ChannelError::ReqSendError
+            }),
+            OverflowPolicy::Reject => self.req_tx.try_send(req).map_err(|e| match e {
+                TrySendError::Full(req) => {
+                    req.discard_reply();
+                    ChannelError::QueueFull
+                }
+                TrySendError::Disconnected(req) => {
+                    req.discard_reply();
+                    ChannelError::ReqSendError
+                }
+            }),
+        }
+    }
+
+    /// 发起请求并阻塞等待结果, 超过 timeout 返回 [`ChannelError::Timeout`]
+    ///
+    /// 等待期间收到的其它响应照常放入队列, 之后仍可以通过 fetch 获取. 超时后请求会被取消
+    pub fn call(&mut self, mut req: Request, timeout: Duration) -> Result<Response, ChannelError> {
+        if !self.running.is_running() {
+            return Err(ChannelError::ServiceStopped);
+        }
+        let id = req.id();
+        let deadline = Instant::now() + timeout;
+        let cancel = CancelToken::new();
+        req.extensions_mut().insert(cancel.clone());
+        req.set_reply(Reply::Client(self.reply.clone()));
+
+        match self.overflow {
+            OverflowPolicy::Block => {
+                self.req_tx
+                    .send_deadline(req, deadline)
+                    .map_err(|e| match e {
+                        SendTimeoutError::Timeout(req) => {
+                            req.discard_reply();
+                            ChannelError::Timeout
+                        }
+                        SendTimeoutError::Disconnected(req) => {
+                            req.discard_reply();
+                            ChannelError::ReqSendError
+                        }
+                    })?
+            }
+            OverflowPolicy::Reject => self.enqueue(req)?,
+        }
+
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            select! {
+                recv(self.res_rx) -> res => match res {
+                    Ok(res) if res.id_ref() == id && res.status_ref().is_finished() => return Ok(res),
+                    Ok(res) => {
+                        self.update_response(res);
+                    }
+                    Err(_) => return Err(ChannelError::ResRecvError),
+                },
+                recv(self.dropped_rx) -> dropped => match dropped {
+                    Ok((dropped, e)) if dropped == id => return Err(e),
+                    Ok((dropped, e)) => {
+                        self.drop_response(dropped, e);
+                    }
+                    Err(_) => return Err(ChannelError::ResRecvError),
+                },
+                default(timeout) => {
+                    cancel.cancel();
+                    return Err(ChannelError::Timeout);
+                }
+            }
+        }
+    }
+
+    /// 用收到的响应更新队列中对应的请求状态
+    ///
+    /// 已经结束的请求不会再被进度更新覆盖
+    fn update_response(&mut self, res: Response) -> bool {
+        let item = self
+            .res_queue
+            .iter_mut()
+            .find(|r| r.id_ref() == res.id_ref());
+        match item {
+            Some(r) if r.status_ref().is_finished() && !res.status_ref().is_finished() => false,
+            Some(r) => {
+                if res.status_ref().is_finished() {
+                    self.cancel_tokens.remove(&res.id_ref());
+                }
+                *r = res;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 响应被 server 丢弃或者请求没有执行, 把对应请求的状态置为失败
+    fn drop_response(&mut self, id: RequestId, e: ChannelError) -> bool {
+        let item = self.res_queue.iter_mut().find(|r| r.id_ref() == id);
+        self.cancel_tokens.remove(&id);
+        // 已经收到最终结果的请求不会被覆盖
+        if let Some(r) = item.filter(|r| !r.status_ref().is_finished()) {
+            *r = e.into_response().uri(std::mem::take(&mut r.uri)).id(id);
+            true
+        } else {
+            false
+        }
     }
 
     /// 处理消息队列
@@ -519,73 +946,258 @@ impl ChannelClient {
     pub fn run_once(&mut self) -> bool {
         let mut recved = false;
         while let Ok(res) = self.res_rx.try_recv() {
-            let item = self
-                .res_queue
-                .iter_mut()
-                .find(|r| r.uri_ref() == res.uri_ref());
-            if let Some(r) = item {
-                *r = res;
-                recved = true;
-            }
+            recved |= self.update_response(res);
         }
-        while let Ok(res) = self.topic_rx.try_recv() {
-            // 只有明确订阅的数据才会被添加到队列中
-            if let Some(queue) = self.topic_queue.get_mut(res.uri_ref()) {
-                queue.push(res);
-                recved = true;
-            }
+        while let Ok((id, e)) = self.dropped_rx.try_recv() {
+            recved |= self.drop_response(id, e);
+        }
+        // 清理已经通过 Subscription 取消的订阅
+        self.topic_queue.retain(|_, queue| !queue.is_closed());
+        for queue in self.topic_queue.values() {
+            recved |= queue.take_fresh();
         }
         recved
     }
 
+    /// 根据 id 获得请求结果
+    pub fn fetch_by_id(&self, id: RequestId) -> Option<&Response> {
+        self.res_queue.iter().find(|res| res.id_ref() == id)
+    }
+
+    /// 清除 id 对应的 response
+    pub fn clean_by_id(&mut self, id: RequestId) {
+        self.res_queue.retain(|res| res.id_ref() != id);
+        self.cancel_tokens.remove(&id);
+    }
+
     /// 根据 uri 获得请求结果
+    ///
+    /// 同一个 uri 有多个请求时, 返回最早发起的那一个, 需要区分时使用 [`ChannelClient::fetch_by_id`]
     pub fn fetch(&self, uri: &str) -> Option<&Response> {
         self.res_queue.iter().find(|res| res.uri_ref() == uri)
     }
 
-    /// 清除 response
+    /// 根据 uri 获得所有请求结果
+    pub fn fetch_all<'a>(&'a self, uri: &'a str) -> impl Iterator<Item = &'a Response> + 'a {
+        self.res_queue
+            .iter()
+            .filter(move |res| res.uri_ref() == uri)
+    }
+
+    /// 清除 uri 对应的所有 response
     pub fn clean(&mut self, uri: &str) {
         self.res_queue.retain(|res| res.uri_ref() != uri);
+        let queue = &self.res_queue;
+        self.cancel_tokens
+            .retain(|id, _| queue.iter().any(|res| res.id_ref() == *id));
     }
 
-    pub fn subject(&mut self, uri: &'static str) {
-        self.topic_queue.insert(uri, Vec::new());
+    /// 订阅主题, 之后发布的数据通过 [`ChannelClient::fetch_topic`] 获取
+    ///
+    /// 支持 MQTT 风格的通配符: `+` 匹配一级, `#` 放在最后匹配剩余的任意级 (包括零级),
+    /// 如 `/sensor/+/temp`, `/sensor/#`
+    pub fn subject(&mut self, uri: impl Into<String>) {
+        let policy = self.topics.default_policy();
+        self.subject_with(uri, policy);
     }
 
-    pub fn fetch_topic(&mut self, uri: &'static str) -> Option<Vec<Response>> {
-        if self.topic_queue.contains_key(uri) {
-            self.topic_queue.insert(uri, Vec::new())
-        } else {
-            None
+    /// 订阅主题, 并指定取出之前数据的缓存方式
+    pub fn subject_with(&mut self, uri: impl Into<String>, policy: TopicPolicy) {
+        self.subscribe_queue(uri.into(), policy);
+    }
+
+    /// 订阅主题, 返回的 [`Subscription`] drop 时取消订阅
+    pub fn subscribe(&mut self, uri: impl Into<String>) -> Subscription {
+        let policy = self.topics.default_policy();
+        self.subscribe_with(uri, policy)
+    }
+
+    /// 同 [`ChannelClient::subscribe`], 并指定取出之前数据的缓存方式
+    pub fn subscribe_with(&mut self, uri: impl Into<String>, policy: TopicPolicy) -> Subscription {
+        let uri = uri.into();
+        let queue = self.subscribe_queue(uri.clone(), policy);
+        Subscription::new(self.topics.clone(), self.topic_id, uri, queue)
+    }
+
+    fn subscribe_queue(&mut self, uri: String, policy: TopicPolicy) -> Arc<TopicQueue> {
+        let queue = self.topics.subscribe(self.topic_id, &uri, policy);
+        self.topic_queue.insert(uri, queue.clone());
+        queue
+    }
+
+    /// 取消订阅, 丢弃还未取出的数据
+    ///
+    /// server 不再向没有订阅任何主题的 client 发送数据
+    pub fn unsubscribe(&mut self, uri: &str) {
+        self.topics.unsubscribe(self.topic_id, uri, None);
+        self.topic_queue.remove(uri);
+    }
+
+    /// 取出订阅的主题收到的数据, uri 与订阅时相同, 通过 [`Response::uri_ref`] 获得数据实际的主题
+    pub fn fetch_topic(&mut self, uri: &str) -> Option<Vec<Response>> {
+        if self.topic_queue.get(uri)?.is_closed() {
+            self.topic_queue.remove(uri);
+            return None;
         }
+        self.topic_queue.get(uri).map(|queue| queue.take())
+    }
+
+    /// 取出订阅的主题收到的数据, 并把 body 按照 json 解析为 T
+    ///
+    /// 没有订阅该主题时返回空的列表
+    pub fn fetch_topic_as<T: DeserializeOwned>(
+        &mut self,
+        uri: &str,
+    ) -> Vec<Result<T, ChannelError>> {
+        self.fetch_topic(uri)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut res| {
+                let data = res.take_body().take()?;
+                serde_json::from_slice(&data).map_err(|_e| ChannelError::ParseJsonError)
+            })
+            .collect()
+    }
+
+    /// 同 [`ChannelClient::fetch_topic_as`], 类型由 [`Topic`] 确定
+    pub fn fetch_typed<T: DeserializeOwned>(
+        &mut self,
+        topic: &Topic<T>,
+    ) -> Vec<Result<T, ChannelError>> {
+        self.fetch_topic_as(topic.uri())
+    }
+
+    /// 服务的运行状态, 包括请求队列深度和工作线程的使用情况
+    pub fn metrics(&self) -> Metrics {
+        self.stats
+            .snapshot(self.req_tx.len(), self.req_tx.capacity())
     }
 
     pub(crate) fn new(
-        req_tx: Sender<Request>,
+        service: &ChannelService,
+        reply: ReplySender,
         res_rx: Receiver<Response>,
-        topic_rx: Receiver<Response>,
+        dropped_rx: Receiver<(RequestId, ChannelError)>,
     ) -> ChannelClient {
+        let topic_id = service.topics.register();
         Self {
-            req_tx,
+            req_tx: service.req_tx.clone(),
+            reply,
             res_rx,
-            topic_rx,
+            dropped_rx,
+            topic_id,
             res_queue: Vec::new(),
+            cancel_tokens: HashMap::new(),
             topic_queue: HashMap::new(),
+            topics: service.topics.clone(),
+            overflow: service.overflow,
+            stats: service.stats.clone(),
+            running: service.running.clone(),
         }
     }
 }
 
-pub struct ChannelService {}
+impl Drop for ChannelClient {
+    fn drop(&mut self) {
+        self.topics.unregister(self.topic_id);
+    }
+}
+
+/// 服务的共享部分, 用于创建新的 [`ChannelClient`]
+///
+/// 通过 [`ServiceHandle::service`] 获取, 可以 clone 后传给其它线程
+#[derive(Clone)]
+pub struct ChannelService {
+    req_tx: Sender<Request>,
+    topics: Arc<TopicBus>,
+    response_capacity: Capacity,
+    response_policy: ResponsePolicy,
+    overflow: OverflowPolicy,
+    stats: Arc<ServerStats>,
+    running: Arc<RunState>,
+}
 
 impl ChannelService {
-    pub fn start(ep: impl Endpoint + 'static + Clone) -> (ChannelClient, ChannelTopic) {
-        let (req_tx, req_rx) = bounded::<Request>(100);
-        let (res_tx, res_rx) = bounded::<Response>(100);
-        let (topic_tx, topic_rx) = bounded::<Response>(100);
-        let client = ChannelClient::new(req_tx, res_rx, topic_rx);
-        let server = ChannelServer::new(req_rx, res_tx);
-        let topic = ChannelTopic::new(topic_tx);
-        server.run(ep);
-        (client, topic)
+    pub(crate) fn new(
+        req_tx: Sender<Request>,
+        topics: Arc<TopicBus>,
+        response_capacity: Capacity,
+        response_policy: ResponsePolicy,
+        overflow: OverflowPolicy,
+        stats: Arc<ServerStats>,
+        running: Arc<RunState>,
+    ) -> Self {
+        Self {
+            req_tx,
+            topics,
+            response_capacity,
+            response_policy,
+            overflow,
+            stats,
+            running,
+        }
+    }
+
+    /// 创建一个新的 client, 共用请求队列, 但有独立的响应队列
+    ///
+    /// 每个 client 只会收到自己发起的请求的响应
+    pub fn new_client(&self) -> ChannelClient {
+        let (res_tx, res_rx) = self.response_capacity.channel::<Response>();
+        let (dropped_tx, dropped_rx) = unbounded::<(RequestId, ChannelError)>();
+        let reply = ReplySender::new(
+            res_tx,
+            &res_rx,
+            dropped_tx,
+            self.response_policy.clone(),
+            self.running.clone(),
+        );
+        ChannelClient::new(self, reply, res_rx, dropped_rx)
+    }
+
+    /// 服务是否还在接受请求
+    pub fn is_running(&self) -> bool {
+        self.running.is_running()
+    }
+
+    /// 使用默认配置启动服务
+    pub fn start(
+        ep: impl Endpoint + 'static + Clone,
+    ) -> (ChannelClient, ChannelTopic, ServiceHandle) {
+        Self::builder().start(ep)
+    }
+
+    /// 启动服务, 由 workers 个工作线程处理请求
+    pub fn start_with_workers(
+        ep: impl Endpoint + 'static + Clone,
+        workers: usize,
+    ) -> (ChannelClient, ChannelTopic, ServiceHandle) {
+        Self::builder().workers(workers).start(ep)
+    }
+
+    /// 配置通道容量, 工作线程等参数后再启动服务
+    pub fn builder() -> ChannelServiceBuilder {
+        ChannelServiceBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam::channel::unbounded;
+
+    use crate::{ChannelError, ChannelService, Param, Route, StatusCode};
+
+    #[test]
+    fn test_request_dropped_in_queue() {
+        let (mut client, _topic, _handle) = ChannelService::start(Route::new());
+        // 模拟服务停止时, 工作线程取完剩余的请求之后才放入队列的请求
+        let (req_tx, req_rx) = unbounded();
+        client.req_tx = req_tx;
+        let id = client.req_with_param("/hello", Param::empty()).unwrap();
+        drop(req_rx);
+
+        assert!(client.run_once());
+        let res = client.fetch_by_id(id).unwrap();
+        let expected = ChannelError::ServiceStopped.to_string();
+        assert!(matches!(res.status_ref(), StatusCode::Fail(msg) if msg == &expected));
     }
 }
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// 工作线程池的运行统计, 由 server 更新, client 读取
+#[derive(Default)]
+pub(crate) struct ServerStats {
+    workers: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicU64,
+}
+
+impl ServerStats {
+    pub(crate) fn new(workers: usize) -> Self {
+        Self {
+            workers: AtomicUsize::new(workers),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn begin(&self) {
+        self.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn end(&self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, queue_depth: usize, queue_capacity: Option<usize>) -> Metrics {
+        Metrics {
+            workers: self.workers.load(Ordering::Relaxed),
+            queue_depth,
+            queue_capacity,
+            active: self.active.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 服务运行状态的快照
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    /// 工作线程数量
+    pub workers: usize,
+    /// 请求队列中等待处理的请求数量
+    pub queue_depth: usize,
+    /// 请求队列的容量, None 表示无界
+    pub queue_capacity: Option<usize>,
+    /// 正在处理的请求数量
+    pub active: usize,
+    /// 已经处理完成的请求数量
+    pub completed: u64,
+}
@@ -1,6 +1,10 @@
 pub use crate::{
     handler,
-    request::{data::Data, json::Json, param::ReqParam},
-    Body, ChannelError, ChannelService, EndpointExt, IntoResponse, Param, Request, Route,
+    request::{
+        cancel::CancelToken, data::Data, json::Json, param::ReqParam, path::Path,
+        progress::Progress, publisher::Publisher,
+    },
+    Body, ChannelError, ChannelService, EndpointExt, IntoResponse, Param, Request, RequestId,
+    Route,
 };
-pub use serde::{Deserialize, Serialize};
\ No newline at end of file
+pub use serde::{Deserialize, Serialize};
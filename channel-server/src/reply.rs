@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use crossbeam::channel::{select, Receiver, Sender, TrySendError};
+
+use crate::{
+    builder::ResponsePolicy, future::ResponseSlot, handle::RunState, ChannelError, RequestId,
+    Response,
+};
+
+/// 随请求一起发给 server, 决定响应发到哪里
+pub(crate) enum Reply {
+    /// 放入 client 的响应队列, 通过 run_once 或 call 取得
+    Client(ReplySender),
+    /// 完成 ChannelClient::send 返回的 ResponseFuture
+    Future(ResponseSlot),
+}
+
+impl Reply {
+    pub(crate) fn send(self, res: Response) {
+        match self {
+            Reply::Client(reply) => reply.send(res),
+            Reply::Future(slot) => slot.send(res),
+        }
+    }
+}
+
+/// server 用于把 Response 发回 client, 响应队列满时按照 ResponsePolicy 处理
+#[derive(Clone)]
+pub(crate) struct ReplySender {
+    res_tx: Sender<Response>,
+    /// 只有 DropOldest 需要, 用于从队列头部丢弃最早的响应
+    res_rx: Option<Receiver<Response>>,
+    /// 通知 client 哪些请求没有结果, 以及原因
+    dropped_tx: Sender<(RequestId, ChannelError)>,
+    policy: ResponsePolicy,
+    /// 服务停止时, ResponsePolicy::Block 不再等待
+    running: Arc<RunState>,
+}
+
+impl ReplySender {
+    pub(crate) fn new(
+        res_tx: Sender<Response>,
+        res_rx: &Receiver<Response>,
+        dropped_tx: Sender<(RequestId, ChannelError)>,
+        policy: ResponsePolicy,
+        running: Arc<RunState>,
+    ) -> Self {
+        let res_rx = matches!(policy, ResponsePolicy::DropOldest).then(|| res_rx.clone());
+        Self {
+            res_tx,
+            res_rx,
+            dropped_tx,
+            policy,
+            running,
+        }
+    }
+
+    pub(crate) fn send(&self, res: Response) {
+        let res = match &self.policy {
+            // client 已经不存在时, 发送失败也没关系
+            ResponsePolicy::Block(timeout) => match self.res_tx.try_send(res) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                // 服务停止时不再等待, 否则工作线程无法退出
+                Err(TrySendError::Full(res)) => select! {
+                    send(self.res_tx, res) -> _ => return,
+                    recv(self.running.stopped()) -> _ => res,
+                    default(*timeout) => res,
+                },
+            },
+            ResponsePolicy::DropOldest => {
+                let mut res = res;
+                loop {
+                    match self.res_tx.try_send(res) {
+                        Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                        Err(TrySendError::Full(r)) => res = r,
+                    }
+                    if let Some(old) = self.res_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                        // 丢弃的只是进度时, 请求之后还会有最终的结果, 不需要通知 client
+                        if old.status_ref().is_finished() {
+                            self.dropped(old);
+                        } else {
+                            log::debug!("progress dropped: {:?}", &old);
+                        }
+                    }
+                }
+            }
+            ResponsePolicy::DeadLetter(_) => match self.res_tx.try_send(res) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                Err(TrySendError::Full(res)) => res,
+            },
+        };
+        self.dropped(res);
+    }
+
+    /// 发送执行进度, 响应队列满时直接丢弃, 不会阻塞 handler
+    pub(crate) fn send_progress(&self, res: Response) {
+        if let Err(TrySendError::Full(res)) = self.res_tx.try_send(res) {
+            log::debug!("progress dropped: {:?}", &res);
+        }
+    }
+
+    /// 请求没有执行就被丢弃 (例如服务停止时还在请求队列中), 通知 client
+    ///
+    /// 与响应被丢弃区分开, handler 没有执行过
+    pub(crate) fn discard(&self, id: RequestId) {
+        log::warn!("request dropped: {:?}", id);
+        self.dropped_tx
+            .send((id, ChannelError::ServiceStopped))
+            .ok();
+    }
+
+    fn dropped(&self, res: Response) {
+        log::warn!("response dropped: {:?}", &res);
+        let id = res.id_ref();
+        if let ResponsePolicy::DeadLetter(hook) = &self.policy {
+            hook(res);
+        }
+        self.dropped_tx
+            .send((id, ChannelError::ResponseDropped))
+            .ok();
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{Body, ChannelError, FromRequest, Request};
+
+/// 请求的取消标记, client 调用 [`ChannelClient::cancel`](crate::ChannelClient::cancel) 后变为已取消
+///
+/// 耗时的 handler 应该定期检查, 取消后尽快返回 [`ChannelError::Cancelled`], 请求的状态为
+/// [`StatusCode::Cancelled`](crate::StatusCode::Cancelled). handler 正常返回时, 即使已经取消也保留它的结果
+///
+/// ```ignore
+/// #[handler]
+/// fn scan(cancel: CancelToken) -> Result<&'static str, ChannelError> {
+///     for line in 0..100 {
+///         cancel.check()?;
+///         // ...
+///     }
+///     Ok("ok")
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<CancelInner>);
+
+#[derive(Debug, Default)]
+struct CancelInner {
+    cancelled: AtomicBool,
+    parent: Option<CancelToken>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 创建一个子标记, 父标记取消时子标记也随之取消, 取消子标记不影响父标记
+    pub fn child(&self) -> Self {
+        Self(Arc::new(CancelInner {
+            cancelled: AtomicBool::new(false),
+            parent: Some(self.clone()),
+        }))
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+            || self.0.parent.as_ref().is_some_and(|p| p.is_cancelled())
+    }
+
+    /// 已取消时返回 [`ChannelError::Cancelled`], 在 handler 中配合 `?` 使用
+    pub fn check(&self) -> Result<(), ChannelError> {
+        match self.is_cancelled() {
+            true => Err(ChannelError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<'a> FromRequest<'a> for CancelToken {
+    fn from_request(req: &'a Request, _body: &mut Body) -> Result<Self, ChannelError> {
+        let token = req.extensions().get::<CancelToken>().ok_or_else(|| {
+            ChannelError::GetDataError(std::any::type_name::<CancelToken>().into())
+        })?;
+        Ok(token.clone())
+    }
+}
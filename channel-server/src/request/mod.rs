@@ -1,4 +1,8 @@
 pub mod string;
 pub mod json;
 pub mod param;
+pub mod path;
 pub mod data;
+pub mod publisher;
+pub mod progress;
+pub mod cancel;
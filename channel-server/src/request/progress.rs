@@ -0,0 +1,56 @@
+use crate::{
+    reply::ReplySender, Body, ChannelError, FromRequest, Request, RequestId, Response, StatusCode,
+};
+
+/// 在 handler 中向 client 报告执行进度
+///
+/// client 通过 [`ChannelClient::fetch`](crate::ChannelClient::fetch) 等获得的响应状态为
+/// [`StatusCode::Pending`], 进度通过 [`Response::progress_ref`] 获取, handler 返回后变为最终的结果.
+/// 通过 [`ChannelClient::send`](crate::ChannelClient::send) 发起的请求只关心最终的结果, 进度会被忽略
+///
+/// ```ignore
+/// #[handler]
+/// fn upgrade(progress: Progress) -> &'static str {
+///     for i in 0..10 {
+///         progress.update(i as f32 / 10.0, format!("写入第 {} 块", i));
+///     }
+///     "ok"
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Progress {
+    id: RequestId,
+    uri: String,
+    reply: Option<ReplySender>,
+}
+
+impl Progress {
+    pub(crate) fn new(id: RequestId, uri: String, reply: Option<ReplySender>) -> Self {
+        Self { id, uri, reply }
+    }
+
+    /// 报告进度, fraction 为 0.0 ~ 1.0
+    ///
+    /// 响应队列满时丢弃该进度
+    pub fn update(&self, fraction: f32, msg: impl Into<String>) {
+        let Some(reply) = &self.reply else {
+            return;
+        };
+        let res = Response::new()
+            .id(self.id)
+            .uri(self.uri.clone())
+            .status(StatusCode::Pending(msg.into()))
+            .progress(fraction);
+        reply.send_progress(res);
+    }
+}
+
+impl<'a> FromRequest<'a> for Progress {
+    fn from_request(req: &'a Request, _body: &mut Body) -> Result<Self, ChannelError> {
+        let progress = req
+            .extensions()
+            .get::<Progress>()
+            .ok_or_else(|| ChannelError::GetDataError(std::any::type_name::<Progress>().into()))?;
+        Ok(progress.clone())
+    }
+}
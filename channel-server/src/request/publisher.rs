@@ -0,0 +1,32 @@
+use std::ops::Deref;
+
+use crate::{Body, ChannelError, ChannelTopic, FromRequest, Request};
+
+/// 在 handler 中发布主题数据, 由 server 注入到每个请求的 Extensions 中
+///
+/// ```ignore
+/// #[handler]
+/// fn record(publisher: Publisher) -> &'static str {
+///     publisher.publish_json("/record/state", &"started").ok();
+///     "ok"
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Publisher(pub ChannelTopic);
+
+impl Deref for Publisher {
+    type Target = ChannelTopic;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> FromRequest<'a> for Publisher {
+    fn from_request(req: &'a Request, _body: &mut Body) -> Result<Self, ChannelError> {
+        let topic = req.extensions().get::<ChannelTopic>().ok_or_else(|| {
+            ChannelError::GetDataError(std::any::type_name::<ChannelTopic>().into())
+        })?;
+        Ok(Publisher(topic.clone()))
+    }
+}
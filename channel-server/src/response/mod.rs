@@ -1,6 +1,6 @@
 use bytes::Bytes;
 
-use crate::{Response, StatusCode, IntoResponse};
+use crate::{ChannelError, Response, StatusCode, IntoResponse};
 
 impl IntoResponse for Response {
     fn into_response(self) -> Response {
@@ -43,3 +43,12 @@ impl IntoResponse for () {
         Response::new().status(StatusCode::ok())
     }
 }
+
+impl<T: IntoResponse> IntoResponse for Result<T, ChannelError> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(res) => res.into_response(),
+            Err(e) => e.into_response(),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::sync::{Arc, RwLock, Weak};
+
+use serde::Serialize;
+
+use crate::{
+    request::{json::Json, path::PathParams},
+    ChannelError, Endpoint, EndpointInfo, IntoResponse, Request, Response,
+};
+
+mod tree;
+
+use tree::RouteTree;
+
+type RouteEndpoint = Arc<dyn Endpoint<Output = Response>>;
+
+/// nest 时用于捕获剩余路径的参数名, 不会出现在 PathParams 中
+const NEST_REST: &str = "--nest-rest";
+
+#[derive(Clone)]
+struct RouteEntry {
+    ep: RouteEndpoint,
+    /// 通过 nest 挂载, 调用前需要去掉路径前缀
+    nested: bool,
+}
+
+/// 注册的路径和对应 Endpoint 的描述信息
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteInfo {
+    pub path: String,
+    #[serde(flatten)]
+    pub info: EndpointInfo,
+}
+
+/// 按照 uri 把请求分发到对应的 Endpoint
+///
+/// 路径支持参数 `:name` 和通配 `*name`, 捕获的值可以通过
+/// [`Path`](crate::request::path::Path) 获取
+///
+/// ```ignore
+/// let motor = Route::new().at("/:id/speed", speed);
+/// let ep = Route::new()
+///     .at("/hello", hello)
+///     .at("/files/*path", files)
+///     .nest("/motor", motor);
+/// ```
+#[derive(Clone)]
+pub struct Route {
+    map: Arc<RwLock<RouteTree<RouteEntry>>>,
+    /// 没有匹配的路径时使用
+    fallback: Arc<RwLock<Option<RouteEndpoint>>>,
+}
+
+impl Route {
+    pub fn new() -> Self {
+        Self {
+            map: Arc::default(),
+            fallback: Arc::default(),
+        }
+    }
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Endpoint for Route {
+    type Output = Response;
+
+    fn call(&self, mut req: Request) -> Result<Self::Output, ChannelError> {
+        // 不能在持有锁的时候执行 handler
+        let (entry, mut params) = {
+            let map = self.map.read().unwrap();
+            match map.find(req.uri_ref()) {
+                Some((entry, params)) => (entry.clone(), params),
+                None => {
+                    drop(map);
+                    let fallback = self.fallback.read().unwrap().clone();
+                    return match fallback {
+                        Some(ep) => ep.call(req),
+                        None => Err(ChannelError::PathNotFoundError(req.uri_ref().into())),
+                    };
+                }
+            }
+        };
+        if entry.nested {
+            // 内层的 Route 只看到去掉前缀后的路径
+            let rest = match params.last() {
+                Some((name, _)) if name == NEST_REST => params.pop().unwrap().1,
+                _ => String::new(),
+            };
+            req.set_uri(format!("/{}", rest));
+        }
+        match req.extensions_mut().get_mut::<PathParams>() {
+            Some(captured) => captured.extend(params),
+            None => {
+                req.extensions_mut().insert(PathParams::new(params));
+            }
+        }
+        entry.ep.call(req)
+    }
+
+    fn paths(&self) -> Vec<RouteInfo> {
+        list_paths(&self.map.read().unwrap())
+    }
+}
+
+impl Route {
+    /// 注册路径, 路径无效或者已经存在时 panic
+    #[must_use]
+    pub fn at(
+        self,
+        path: impl Into<String>,
+        ep: impl Endpoint<Output = Response> + 'static,
+    ) -> Self {
+        if let Err(err) = self.insert(path, ep) {
+            panic!("{}", err);
+        }
+        self
+    }
+
+    /// 把 ep (通常是另一个 Route) 挂载到 prefix 下, 路径无效或者已经存在时 panic
+    ///
+    /// ep 收到的请求 uri 去掉了 prefix, 例如挂载到 `/motor` 时, `/motor/3/speed` 变为 `/3/speed`,
+    /// prefix 中捕获的参数仍然可以通过 Path 获取
+    #[must_use]
+    pub fn nest(
+        self,
+        prefix: impl Into<String>,
+        ep: impl Endpoint<Output = Response> + 'static,
+    ) -> Self {
+        if let Err(err) = self.insert_nest(prefix, ep) {
+            panic!("{}", err);
+        }
+        self
+    }
+
+    /// 没有匹配的路径时, 把请求交给 ep 处理, 例如转发给其它服务, 或者兼容改名前的 uri
+    ///
+    /// 默认返回 [`ChannelError::PathNotFoundError`]
+    #[must_use]
+    pub fn fallback(self, ep: impl Endpoint<Output = Response> + 'static) -> Self {
+        *self.fallback.write().unwrap() = Some(Arc::new(ep));
+        self
+    }
+
+    /// 自定义没有匹配的路径时返回的响应
+    ///
+    /// ```ignore
+    /// let ep = Route::new().at("/hello", hello).not_found(|req| {
+    ///     Response::new()
+    ///         .status(StatusCode::Fail(format!("unknown uri: {}", req.uri_ref())))
+    /// });
+    /// ```
+    #[must_use]
+    pub fn not_found<F>(self, f: F) -> Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.fallback(NotFound(f))
+    }
+
+    /// 列出注册的所有路径, nest 挂载的 Route 会展开
+    pub fn paths(&self) -> Vec<RouteInfo> {
+        list_paths(&self.map.read().unwrap())
+    }
+
+    /// 在 path 注册一个以 json 返回 [`Route::paths`] 的 Endpoint, 用于调试
+    ///
+    /// ```ignore
+    /// let ep = Route::new().at("/hello", hello).paths_endpoint("/_routes");
+    /// ```
+    #[must_use]
+    pub fn paths_endpoint(self, path: impl Into<String>) -> Self {
+        // 使用 Weak, 避免 Route 引用自己
+        let ep = PathsEndpoint {
+            map: Arc::downgrade(&self.map),
+        };
+        self.at(path, ep)
+    }
+
+    /// 在运行中注册路径, 所有 clone 出来的 Route 都会生效
+    pub fn insert(
+        &self,
+        path: impl Into<String>,
+        ep: impl Endpoint<Output = Response> + 'static,
+    ) -> Result<(), ChannelError> {
+        let entry = RouteEntry {
+            ep: Arc::new(ep),
+            nested: false,
+        };
+        insert_entry(&mut self.map.write().unwrap(), &path.into(), entry)
+    }
+
+    /// 在运行中把 ep 挂载到 prefix 下, 与 [`Route::nest`] 相同
+    pub fn insert_nest(
+        &self,
+        prefix: impl Into<String>,
+        ep: impl Endpoint<Output = Response> + 'static,
+    ) -> Result<(), ChannelError> {
+        let entry = RouteEntry {
+            ep: Arc::new(ep),
+            nested: true,
+        };
+        insert_entry(&mut self.map.write().unwrap(), &prefix.into(), entry)
+    }
+
+    /// 移除路径, 路径需要与注册时完全相同, 包括参数名
+    ///
+    /// 正在执行的请求不受影响
+    pub fn remove(&self, path: &str) -> Result<(), ChannelError> {
+        remove_entry(&mut self.map.write().unwrap(), path).map(|_| ())
+    }
+
+    /// 替换已经注册的路径对应的 Endpoint, 通过 nest 挂载的仍然按照 nest 处理
+    pub fn replace(
+        &self,
+        path: impl Into<String>,
+        ep: impl Endpoint<Output = Response> + 'static,
+    ) -> Result<(), ChannelError> {
+        let path = path.into();
+        let mut map = self.map.write().unwrap();
+        let old = remove_entry(&mut map, &path)?;
+        let entry = RouteEntry {
+            ep: Arc::new(ep),
+            nested: old.nested,
+        };
+        insert_entry(&mut map, &path, entry)
+    }
+}
+
+struct NotFound<F>(F);
+
+impl<F> Endpoint for NotFound<F>
+where
+    F: Fn(&Request) -> Response + Send + Sync,
+{
+    type Output = Response;
+
+    fn call(&self, req: Request) -> Result<Self::Output, ChannelError> {
+        Ok((self.0)(&req))
+    }
+}
+
+struct PathsEndpoint {
+    map: Weak<RwLock<RouteTree<RouteEntry>>>,
+}
+
+impl Endpoint for PathsEndpoint {
+    type Output = Response;
+
+    fn call(&self, req: Request) -> Result<Self::Output, ChannelError> {
+        let map = self
+            .map
+            .upgrade()
+            .ok_or_else(|| ChannelError::PathNotFoundError(req.uri_ref().into()))?;
+        let paths = list_paths(&map.read().unwrap());
+        Ok(Json(paths).into_response())
+    }
+
+    fn info(&self) -> EndpointInfo {
+        EndpointInfo {
+            description: Some("列出注册的所有路径".into()),
+            params: Vec::new(),
+            response: Some("Json<Vec<RouteInfo>>"),
+        }
+    }
+}
+
+fn list_paths(map: &RouteTree<RouteEntry>) -> Vec<RouteInfo> {
+    let mut paths = Vec::new();
+    for (path, entry) in map.entries() {
+        if !entry.nested {
+            paths.push(RouteInfo {
+                path,
+                info: entry.ep.info(),
+            });
+            continue;
+        }
+        // 每个 nest 注册了两次, 只展开 prefix 本身
+        if path.ends_with(NEST_REST) {
+            continue;
+        }
+        let nested = entry.ep.paths();
+        if nested.is_empty() {
+            paths.push(RouteInfo {
+                path,
+                info: entry.ep.info(),
+            });
+            continue;
+        }
+        let prefix = path.trim_end_matches('/');
+        for route in nested {
+            let path = match route.path.as_str() {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                inner => format!("{}{}", prefix, inner),
+            };
+            paths.push(RouteInfo {
+                path,
+                info: route.info,
+            });
+        }
+    }
+    paths
+}
+
+/// nest 时除了 prefix 本身, 还要匹配 prefix 下的所有路径
+fn nest_rest(prefix: &str) -> String {
+    format!("{}/*{}", prefix.trim_end_matches('/'), NEST_REST)
+}
+
+fn insert_entry(
+    map: &mut RouteTree<RouteEntry>,
+    path: &str,
+    entry: RouteEntry,
+) -> Result<(), ChannelError> {
+    if !entry.nested {
+        return map.insert(path, entry);
+    }
+    map.insert(path, entry.clone())?;
+    let res = map.insert(&nest_rest(path), entry);
+    if res.is_err() {
+        map.remove(path);
+    }
+    res
+}
+
+fn remove_entry(map: &mut RouteTree<RouteEntry>, path: &str) -> Result<RouteEntry, ChannelError> {
+    let entry = map
+        .remove(path)
+        .ok_or_else(|| ChannelError::PathNotFoundError(path.into()))?;
+    if entry.nested {
+        map.remove(&nest_rest(path));
+    }
+    Ok(entry)
+}
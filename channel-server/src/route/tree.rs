@@ -0,0 +1,319 @@
+use ahash::AHashMap;
+
+use crate::ChannelError;
+
+/// 按 `/` 分段的路由树
+///
+/// 每一段可以是:
+/// - 静态字符串, 如 `motor`
+/// - 参数 `:name`, 匹配任意一段
+/// - 通配 `*name`, 匹配剩余的一段或多段, 只能出现在最后
+///
+/// 匹配时优先级为 静态 > 参数 > 通配
+pub(crate) struct RouteTree<T> {
+    root: Node<T>,
+}
+
+struct Node<T> {
+    value: Option<T>,
+    statics: AHashMap<String, Node<T>>,
+    param: Option<(String, Box<Node<T>>)>,
+    catch_all: Option<(String, T)>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            statics: AHashMap::new(),
+            param: None,
+            catch_all: None,
+        }
+    }
+}
+
+enum Segment<'a> {
+    Static(&'a str),
+    Param(&'a str),
+    CatchAll(&'a str),
+}
+
+/// 把路径拆分为段, 忽略多余的 `/`
+pub(crate) fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+fn parse_path(path: &str) -> Result<Vec<Segment<'_>>, ChannelError> {
+    let segments = split_path(path).collect::<Vec<_>>();
+    let last = segments.len().saturating_sub(1);
+    segments
+        .into_iter()
+        .enumerate()
+        .map(|(i, seg)| {
+            if let Some(name) = seg.strip_prefix(':') {
+                if name.is_empty() {
+                    return Err(ChannelError::InvalidPath(format!(
+                        "empty param name: {}",
+                        path
+                    )));
+                }
+                Ok(Segment::Param(name))
+            } else if let Some(name) = seg.strip_prefix('*') {
+                if name.is_empty() || i != last {
+                    return Err(ChannelError::InvalidPath(format!(
+                        "catch-all must be named and at the end: {}",
+                        path
+                    )));
+                }
+                Ok(Segment::CatchAll(name))
+            } else {
+                Ok(Segment::Static(seg))
+            }
+        })
+        .collect()
+}
+
+impl<T> Default for RouteTree<T> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+        }
+    }
+}
+
+impl<T> RouteTree<T> {
+    pub(crate) fn insert(&mut self, path: &str, value: T) -> Result<(), ChannelError> {
+        let duplicate = || ChannelError::InvalidPath(format!("duplicate path: {}", path));
+        let mut node = &mut self.root;
+        for seg in parse_path(path)? {
+            node = match seg {
+                Segment::Static(s) => node.statics.entry(s.to_string()).or_default(),
+                Segment::Param(name) => {
+                    let (param, child) = node
+                        .param
+                        .get_or_insert_with(|| (name.to_string(), Box::default()));
+                    if param != name {
+                        return Err(ChannelError::InvalidPath(format!(
+                            "conflicting param name `{}` and `{}`: {}",
+                            param, name, path
+                        )));
+                    }
+                    child
+                }
+                Segment::CatchAll(name) => {
+                    if node.catch_all.is_some() {
+                        return Err(duplicate());
+                    }
+                    node.catch_all = Some((name.to_string(), value));
+                    return Ok(());
+                }
+            };
+        }
+        if node.value.is_some() {
+            return Err(duplicate());
+        }
+        node.value = Some(value);
+        Ok(())
+    }
+
+    /// 移除注册时的路径, 参数名需要一致
+    pub(crate) fn remove(&mut self, path: &str) -> Option<T> {
+        let segments = parse_path(path).ok()?;
+        self.root.remove(&segments)
+    }
+
+    /// 所有注册的路径和对应的值, 按路径排序
+    pub(crate) fn entries(&self) -> Vec<(String, &T)> {
+        let mut entries = Vec::new();
+        self.root.collect(String::new(), &mut entries);
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// 查找路径对应的值, 同时返回捕获的参数
+    pub(crate) fn find(&self, path: &str) -> Option<(&T, Vec<(String, String)>)> {
+        let segments = split_path(path).collect::<Vec<_>>();
+        let mut params = Vec::new();
+        let value = self.root.find(&segments, &mut params)?;
+        Some((value, params))
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.value.is_none()
+            && self.statics.is_empty()
+            && self.param.is_none()
+            && self.catch_all.is_none()
+    }
+
+    fn collect<'a>(&'a self, path: String, entries: &mut Vec<(String, &'a T)>) {
+        if let Some(value) = &self.value {
+            let path = if path.is_empty() {
+                "/".into()
+            } else {
+                path.clone()
+            };
+            entries.push((path, value));
+        }
+        for (seg, child) in &self.statics {
+            child.collect(format!("{}/{}", path, seg), entries);
+        }
+        if let Some((name, child)) = &self.param {
+            child.collect(format!("{}/:{}", path, name), entries);
+        }
+        if let Some((name, value)) = &self.catch_all {
+            entries.push((format!("{}/*{}", path, name), value));
+        }
+    }
+
+    /// 移除后清理空的节点, 避免影响之后注册不同名称的参数
+    fn remove(&mut self, segments: &[Segment<'_>]) -> Option<T> {
+        let (first, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => return self.value.take(),
+        };
+        match first {
+            Segment::Static(s) => {
+                let child = self.statics.get_mut(*s)?;
+                let value = child.remove(rest);
+                if child.is_empty() {
+                    self.statics.remove(*s);
+                }
+                value
+            }
+            Segment::Param(name) => {
+                let (param, child) = self.param.as_mut()?;
+                if param != name {
+                    return None;
+                }
+                let value = child.remove(rest);
+                if child.is_empty() {
+                    self.param = None;
+                }
+                value
+            }
+            Segment::CatchAll(name) => match &self.catch_all {
+                Some((param, _)) if param == name => self.catch_all.take().map(|(_, v)| v),
+                _ => None,
+            },
+        }
+    }
+
+    fn find(&self, segments: &[&str], params: &mut Vec<(String, String)>) -> Option<&T> {
+        let (first, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => return self.value.as_ref(),
+        };
+
+        if let Some(value) = self
+            .statics
+            .get(*first)
+            .and_then(|child| child.find(rest, params))
+        {
+            return Some(value);
+        }
+
+        if let Some((name, child)) = &self.param {
+            params.push((name.clone(), first.to_string()));
+            if let Some(value) = child.find(rest, params) {
+                return Some(value);
+            }
+            params.pop();
+        }
+
+        let (name, value) = self.catch_all.as_ref()?;
+        params.push((name.clone(), segments.join("/")));
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(paths: &[&'static str]) -> RouteTree<&'static str> {
+        let mut tree = RouteTree::default();
+        for path in paths {
+            tree.insert(path, *path).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_static() {
+        let tree = tree(&["/", "/hello", "/hello/world"]);
+        assert_eq!(*tree.find("/").unwrap().0, "/");
+        assert_eq!(*tree.find("/hello").unwrap().0, "/hello");
+        assert_eq!(*tree.find("/hello/world/").unwrap().0, "/hello/world");
+        assert!(tree.find("/world").is_none());
+    }
+
+    #[test]
+    fn test_params() {
+        let tree = tree(&["/motor/:id/speed", "/motor/0/speed", "/files/*path"]);
+
+        let (value, params) = tree.find("/motor/3/speed").unwrap();
+        assert_eq!(*value, "/motor/:id/speed");
+        assert_eq!(params, vec![("id".to_string(), "3".to_string())]);
+
+        // 静态的优先
+        let (value, params) = tree.find("/motor/0/speed").unwrap();
+        assert_eq!(*value, "/motor/0/speed");
+        assert!(params.is_empty());
+
+        let (value, params) = tree.find("/files/a/b.txt").unwrap();
+        assert_eq!(*value, "/files/*path");
+        assert_eq!(params, vec![("path".to_string(), "a/b.txt".to_string())]);
+
+        assert!(tree.find("/files").is_none());
+        assert!(tree.find("/motor/3").is_none());
+    }
+
+    #[test]
+    fn test_backtrack() {
+        let tree = tree(&["/a/:x/c", "/a/b/d", "/a/*rest"]);
+        assert_eq!(*tree.find("/a/b/c").unwrap().0, "/a/:x/c");
+        assert_eq!(*tree.find("/a/b/d").unwrap().0, "/a/b/d");
+        assert_eq!(*tree.find("/a/b/e").unwrap().0, "/a/*rest");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut tree = tree(&["/a/:x", "/b/*rest"]);
+        assert!(tree.insert("/a/:x", "").is_err());
+        assert!(tree.insert("/a/:y/z", "").is_err());
+        assert!(tree.insert("/b/*other", "").is_err());
+        assert!(tree.insert("/c/*rest/d", "").is_err());
+        assert!(tree.insert("/c/:", "").is_err());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut tree = tree(&["/a/:x", "/a/:x/b", "/c/*rest"]);
+        assert!(tree.remove("/a/:y").is_none());
+        assert_eq!(tree.remove("/a/:x"), Some("/a/:x"));
+        assert!(tree.find("/a/1").is_none());
+        assert!(tree.find("/a/1/b").is_some());
+
+        assert_eq!(tree.remove("/a/:x/b"), Some("/a/:x/b"));
+        assert_eq!(tree.remove("/c/*rest"), Some("/c/*rest"));
+        assert!(tree.find("/c/d").is_none());
+
+        // 空的节点已经清理, 可以使用新的参数名
+        tree.insert("/a/:y", "/a/:y").unwrap();
+        assert_eq!(*tree.find("/a/1").unwrap().0, "/a/:y");
+    }
+
+    #[test]
+    fn test_entries() {
+        let paths = ["/", "/a/:x", "/a/:x/b", "/a/c", "/d/*rest"];
+        let tree = tree(&paths);
+        let entries = tree.entries();
+        assert_eq!(entries.len(), paths.len());
+        for ((path, value), expected) in entries.into_iter().zip(paths) {
+            assert_eq!(path, expected);
+            assert_eq!(*value, expected);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! 执行 async handler
+//!
+//! `#[handler]` 修饰 async fn 时, 生成的 [`Endpoint::call`](crate::Endpoint::call) 通过 [`block_on`] 在工作线程中等待 handler 完成.
+//! 启用 `tokio` feature 后, handler 运行在服务持有的 tokio runtime 上, 可以直接使用 tokio 的 io, 定时器等;
+//! 否则使用一个简单的执行器, 只能执行不依赖特定 runtime 的 Future.
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake},
+    thread::Thread,
+};
+
+use crate::Request;
+
+/// 在当前线程中等待 fut 完成, 由 `#[handler]` 生成的代码调用
+///
+/// 不经过 server 直接调用 Endpoint 时请求中没有 runtime, 不使用当前线程所在的 runtime,
+/// 在 async 代码中调用 block_on 会 panic
+#[doc(hidden)]
+pub fn block_on<F: Future>(req: &Request, fut: F) -> F::Output {
+    #[cfg(feature = "tokio")]
+    if let Some(handle) = req.extensions().get::<tokio::runtime::Handle>() {
+        return handle.block_on(fut);
+    }
+    #[cfg(not(feature = "tokio"))]
+    let _ = req;
+    park_on(fut)
+}
+
+/// 不依赖任何 runtime, 通过 park/unpark 当前线程等待 fut 完成
+fn park_on<F: Future>(fut: F) -> F::Output {
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut fut = pin!(fut);
+    let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// 服务使用的 tokio runtime, 自己创建的 runtime 在所有工作线程退出后关闭
+#[cfg(feature = "tokio")]
+pub(crate) struct AsyncRuntime {
+    _owned: Option<tokio::runtime::Runtime>,
+    handle: tokio::runtime::Handle,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRuntime {
+    pub(crate) fn new(handle: Option<tokio::runtime::Handle>, thread_name: &str) -> Self {
+        match handle {
+            Some(handle) => Self {
+                _owned: None,
+                handle,
+            },
+            None => {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .thread_name(format!("{}-async", thread_name))
+                    .build()
+                    .expect("failed to build tokio runtime");
+                Self {
+                    handle: runtime.handle().clone(),
+                    _owned: Some(runtime),
+                }
+            }
+        }
+    }
+
+    pub(crate) fn handle(&self) -> &tokio::runtime::Handle {
+        &self.handle
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crossbeam::channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+
+use crate::{
+    request::cancel::CancelToken, ChannelError, Endpoint, EndpointInfo, IntoResponse, Middleware,
+    Request, Response, RouteInfo,
+};
+
+/// Middleware for failing requests that take longer than the given duration.
+///
+/// 内部的 Endpoint 在 Timeout 自己的线程池中执行, 线程名为创建它的工作线程名加上 `-timeout`.
+/// 执行完的线程会被之后的请求重复使用, 空闲 60 秒后退出; 没有空闲线程时为请求创建新的线程.
+/// 超时后请求立即以 [`ChannelError::Timeout`] 失败, 同时取消请求的 [`CancelToken`].
+///
+/// 线程无法被强制结束, 超时的 handler 会一直占用它的线程, 直到检查到 CancelToken 后返回.
+/// 这样的线程达到 [`Timeout::max_timed_out`] 时, 新的请求直接以 [`ChannelError::TooManyTimedOut`] 失败
+///
+/// ```ignore
+/// let ep = Route::new().at("/motor/home", home.with(Timeout::new(Duration::from_secs(3))));
+/// ```
+pub struct Timeout {
+    duration: Duration,
+    max_timed_out: usize,
+}
+
+impl Timeout {
+    pub fn new(duration: Duration) -> Self {
+        Timeout {
+            duration,
+            max_timed_out: 8,
+        }
+    }
+
+    /// 超时后仍在执行的 handler 的上限, 默认 8
+    #[must_use]
+    pub fn max_timed_out(mut self, n: usize) -> Self {
+        self.max_timed_out = n;
+        self
+    }
+}
+
+impl<E> Middleware<E> for Timeout
+where
+    E: Endpoint + 'static,
+{
+    type Output = TimeoutEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        TimeoutEndpoint {
+            inner: Arc::new(ep),
+            duration: self.duration,
+            max_timed_out: self.max_timed_out,
+            timed_out: Arc::new(AtomicUsize::new(0)),
+            pool: Arc::new(Pool::new()),
+        }
+    }
+}
+
+pub struct TimeoutEndpoint<E> {
+    inner: Arc<E>,
+    duration: Duration,
+    max_timed_out: usize,
+    /// 超时后仍在执行的 handler 数量, 所有工作线程共用
+    timed_out: Arc<AtomicUsize>,
+    /// 执行内部 Endpoint 的线程, 所有工作线程共用
+    pool: Arc<Pool>,
+}
+
+impl<E> Clone for TimeoutEndpoint<E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            duration: self.duration,
+            max_timed_out: self.max_timed_out,
+            timed_out: self.timed_out.clone(),
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// 线程空闲多久后退出
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 执行内部 Endpoint 的线程池, 线程数量随超时的 handler 和并发的请求增长, 空闲后退出
+struct Pool {
+    job_tx: Sender<Job>,
+    job_rx: Receiver<Job>,
+    /// 没有被占用, 正在等待任务的线程数量
+    idle: AtomicUsize,
+}
+
+impl Pool {
+    fn new() -> Self {
+        let (job_tx, job_rx) = unbounded();
+        Self {
+            job_tx,
+            job_rx,
+            idle: AtomicUsize::new(0),
+        }
+    }
+
+    fn execute(self: &Arc<Self>, job: Job) -> Result<(), ChannelError> {
+        // 先占用一个空闲的线程, 被占用的线程不会退出, 一定会取走这个任务
+        if self.claim() {
+            self.job_tx.send(job).ok();
+            return Ok(());
+        }
+        let pool = self.clone();
+        let name = format!(
+            "{}-timeout",
+            std::thread::current().name().unwrap_or("channel")
+        );
+        std::thread::Builder::new().name(name).spawn(move || {
+            job();
+            pool.work();
+        })?;
+        Ok(())
+    }
+
+    fn claim(&self) -> bool {
+        self.idle
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    fn work(&self) {
+        self.idle.fetch_add(1, Ordering::AcqRel);
+        loop {
+            match self.job_rx.recv_timeout(IDLE_TIMEOUT) {
+                Ok(job) => {
+                    job();
+                    self.idle.fetch_add(1, Ordering::AcqRel);
+                }
+                // 自己没有被占用时退出, 否则任务马上就会放入队列, 继续等待
+                Err(_) => {
+                    if self.claim() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 一次调用的状态, 执行线程和等待的工作线程通过它确定谁负责更新 timed_out
+#[derive(Default)]
+struct CallState {
+    finished: bool,
+    abandoned: bool,
+}
+
+impl<E> Endpoint for TimeoutEndpoint<E>
+where
+    E: Endpoint + 'static,
+{
+    type Output = Response;
+
+    fn call(&self, mut req: Request) -> Result<Self::Output, ChannelError> {
+        // 使用子标记, 超时取消时不会把请求的状态变为已取消
+        let cancel = match req.extensions().get::<CancelToken>() {
+            Some(parent) => parent.child(),
+            None => CancelToken::new(),
+        };
+        req.extensions_mut().insert(cancel.clone());
+
+        if self.timed_out.load(Ordering::Acquire) >= self.max_timed_out {
+            return Err(ChannelError::TooManyTimedOut);
+        }
+
+        let (tx, rx) = bounded(1);
+        let inner = self.inner.clone();
+        let state = Arc::new(Mutex::new(CallState::default()));
+        let thread_state = state.clone();
+        let timed_out = self.timed_out.clone();
+        self.pool.execute(Box::new(move || {
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                inner.call(req).map(IntoResponse::into_response)
+            }));
+            let abandoned = {
+                let mut state = thread_state.lock().unwrap();
+                state.finished = true;
+                state.abandoned
+            };
+            if abandoned {
+                timed_out.fetch_sub(1, Ordering::AcqRel);
+            } else {
+                tx.send(res).ok();
+            }
+        }))?;
+
+        let res = match rx.recv_timeout(self.duration) {
+            Err(RecvTimeoutError::Timeout) => {
+                let mut state = state.lock().unwrap();
+                if !state.finished {
+                    state.abandoned = true;
+                    self.timed_out.fetch_add(1, Ordering::AcqRel);
+                    cancel.cancel();
+                    return Err(ChannelError::Timeout);
+                }
+                // 刚好在超时时结束, 结果马上就会发过来
+                drop(state);
+                rx.recv().map_err(|_e| ChannelError::ResRecvError)
+            }
+            res => res.map_err(|_e| ChannelError::ResRecvError),
+        };
+        match res? {
+            Ok(res) => res,
+            // 交给工作线程按照 handler 的 panic 处理
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    fn info(&self) -> EndpointInfo {
+        self.inner.info()
+    }
+
+    fn paths(&self) -> Vec<RouteInfo> {
+        self.inner.paths()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_reuse() {
+        let pool = Arc::new(Pool::new());
+        let (tx, rx) = bounded(2);
+        for _ in 0..2 {
+            let tx = tx.clone();
+            pool.execute(Box::new(move || {
+                tx.send(std::thread::current().id()).ok();
+            }))
+            .unwrap();
+            // 等待线程回到空闲状态
+            while pool.idle.load(Ordering::Acquire) == 0 {
+                std::thread::yield_now();
+            }
+        }
+        assert_eq!(rx.recv().unwrap(), rx.recv().unwrap());
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Formatter},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex, RwLock,
+    },
+};
+
+use serde::Serialize;
+
+use crate::{builder::TopicPolicy, ChannelError, Response};
+
+/// 带有数据类型的主题, 发布者和订阅者共用同一个定义, 编译时保证类型一致
+///
+/// ```ignore
+/// const VIDEO: Topic<Frame> = Topic::new("/topic/video");
+///
+/// topic.publish_typed(&VIDEO, &frame)?;
+/// client.subject(VIDEO.uri());
+/// let frames = client.fetch_typed(&VIDEO);
+/// ```
+pub struct Topic<T> {
+    uri: &'static str,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Topic<T> {
+    pub const fn new(uri: &'static str) -> Self {
+        Self {
+            uri,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn uri(&self) -> &'static str {
+        self.uri
+    }
+}
+
+impl<T> Clone for Topic<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Topic<T> {}
+
+impl<T> Debug for Topic<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Topic").field(&self.uri).finish()
+    }
+}
+
+/// 用于发布主题数据, 每个订阅了该主题的 client 都会收到一份
+#[derive(Clone)]
+pub struct ChannelTopic {
+    bus: Arc<TopicBus>,
+}
+
+impl ChannelTopic {
+    pub(crate) fn new(bus: Arc<TopicBus>) -> Self {
+        Self { bus }
+    }
+
+    /// 发布数据, 订阅的队列满时按照订阅时的 [`TopicPolicy`] 处理
+    ///
+    /// 只发给订阅了该主题的 client, 没有订阅者时直接丢弃
+    pub fn publish(&self, res: Response) {
+        self.bus.publish(res, false);
+    }
+
+    /// 把 value 序列化为 json 作为 body 发布
+    pub fn publish_json<T: Serialize>(&self, uri: &str, value: &T) -> Result<(), ChannelError> {
+        let data = serde_json::to_vec(value)
+            .map_err(|e| ChannelError::SerializeJsonError(e.to_string()))?;
+        self.publish(Response::topic(uri).body(data.into()));
+        Ok(())
+    }
+
+    /// 同 [`ChannelTopic::publish_json`], 类型由 [`Topic`] 确定
+    pub fn publish_typed<T: Serialize>(
+        &self,
+        topic: &Topic<T>,
+        value: &T,
+    ) -> Result<(), ChannelError> {
+        self.publish_json(topic.uri(), value)
+    }
+
+    /// 发布数据, 并保留该主题最新的一条数据, 之后订阅的 client 会立即收到
+    pub fn publish_retained(&self, res: Response) {
+        self.bus.publish(res, true);
+    }
+
+    /// 清除主题保留的数据
+    pub fn clear_retained(&self, uri: &str) {
+        self.bus.retained.lock().unwrap().remove(uri);
+    }
+
+    /// 是否有 client 订阅了该主题, 数据的生成开销较大时可以先检查
+    pub fn has_subscribers(&self, uri: &str) -> bool {
+        self.bus
+            .subscribers
+            .read()
+            .unwrap()
+            .iter()
+            .any(|s| s.matches(uri))
+    }
+}
+
+/// 订阅的句柄, drop 时取消订阅
+///
+/// 通过 [`ChannelClient::subscribe`](crate::ChannelClient::subscribe) 获得
+#[must_use = "drop 时会取消订阅"]
+pub struct Subscription {
+    bus: Arc<TopicBus>,
+    id: u64,
+    uri: String,
+    /// 之后重新订阅同一个主题时, 不会影响新的订阅
+    queue: Arc<TopicQueue>,
+}
+
+impl Subscription {
+    pub(crate) fn new(bus: Arc<TopicBus>, id: u64, uri: String, queue: Arc<TopicQueue>) -> Self {
+        Self {
+            bus,
+            id,
+            uri,
+            queue,
+        }
+    }
+
+    /// 订阅的主题
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.id, &self.uri, Some(&self.queue));
+    }
+}
+
+/// 一个订阅的数据队列, 由发布者写入, client 取出
+pub(crate) struct TopicQueue {
+    policy: TopicPolicy,
+    /// 服务已经停止, TopicPolicy::Block 不再阻塞, 与 TopicBus 共用
+    stopped: Arc<AtomicBool>,
+    state: Mutex<QueueState>,
+    /// TopicPolicy::Block 时, 等待 client 取走数据
+    not_full: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    list: VecDeque<Response>,
+    /// 上次 run_once 之后是否收到新的数据
+    fresh: bool,
+    /// 已经取消订阅
+    closed: bool,
+}
+
+impl TopicQueue {
+    fn new(policy: TopicPolicy, stopped: Arc<AtomicBool>) -> Self {
+        Self {
+            policy,
+            stopped,
+            state: Mutex::new(QueueState::default()),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// 放入数据, wait 为 false 时即使是 TopicPolicy::Block 也不会阻塞, 队列满时丢弃
+    fn push(&self, res: Response, wait: bool) {
+        let mut state = self.state.lock().unwrap();
+        match self.policy {
+            TopicPolicy::KeepLatest(n) => {
+                while state.list.len() >= n.max(1) {
+                    state.list.pop_front();
+                }
+            }
+            TopicPolicy::Latest => state.list.clear(),
+            TopicPolicy::DropNewest(n) => {
+                // 高频的主题跟不上时每条都会丢弃, 不使用 warn 以免刷屏
+                if state.list.len() >= n.max(1) {
+                    log::debug!("topic dropped: {:?}", &res);
+                    return;
+                }
+            }
+            TopicPolicy::Block(n) => {
+                let wait = wait && !self.stopped.load(Ordering::Acquire);
+                if !wait && state.list.len() >= n.max(1) {
+                    return;
+                }
+                while state.list.len() >= n.max(1)
+                    && !state.closed
+                    && !self.stopped.load(Ordering::Acquire)
+                {
+                    state = self.not_full.wait(state).unwrap();
+                }
+            }
+            TopicPolicy::Unbounded => {}
+        }
+        if !state.closed {
+            state.list.push_back(res);
+            state.fresh = true;
+        }
+    }
+
+    /// 取出所有数据
+    pub(crate) fn take(&self) -> Vec<Response> {
+        let mut state = self.state.lock().unwrap();
+        state.fresh = false;
+        let list = state.list.drain(..).collect();
+        self.not_full.notify_all();
+        list
+    }
+
+    /// 是否收到新的数据, 同时清除标记
+    pub(crate) fn take_fresh(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().fresh)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// 唤醒阻塞的发布者, 重新检查是否需要等待
+    fn wake(&self) {
+        let _state = self.state.lock().unwrap();
+        self.not_full.notify_all();
+    }
+
+    /// 取消订阅, 唤醒阻塞的发布者
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_full.notify_all();
+    }
+}
+
+/// 所有订阅者的注册表, 发布时把数据复制到每个订阅的队列中
+pub(crate) struct TopicBus {
+    subscribers: RwLock<Vec<Subscriber>>,
+    /// 每个主题保留的最新数据, 需要在 subscribers 之后加锁
+    retained: Mutex<HashMap<String, Response>>,
+    /// 没有指定 TopicPolicy 时, 订阅使用的默认值
+    policy: TopicPolicy,
+    next_id: AtomicU64,
+    /// 服务已经停止
+    stopped: Arc<AtomicBool>,
+}
+
+/// 一个 client 的所有订阅
+struct Subscriber {
+    id: u64,
+    topics: HashMap<String, Arc<TopicQueue>>,
+}
+
+impl Subscriber {
+    fn matches(&self, uri: &str) -> bool {
+        self.topics.keys().any(|t| matches(t, uri))
+    }
+}
+
+impl TopicBus {
+    pub(crate) fn new(policy: TopicPolicy) -> Self {
+        Self {
+            subscribers: RwLock::new(Vec::new()),
+            retained: Mutex::new(HashMap::new()),
+            policy,
+            next_id: AtomicU64::new(1),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 服务停止, 唤醒因为 TopicPolicy::Block 阻塞的发布者, 之后队列满时直接丢弃
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        let subscribers = self.subscribers.read().unwrap();
+        subscribers
+            .iter()
+            .flat_map(|s| s.topics.values())
+            .for_each(|queue| queue.wake());
+    }
+
+    pub(crate) fn default_policy(&self) -> TopicPolicy {
+        self.policy
+    }
+
+    /// 注册一个订阅者, 返回订阅者的 id
+    pub(crate) fn register(&self) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.subscribers.write().unwrap().push(Subscriber {
+            id,
+            topics: HashMap::new(),
+        });
+        id
+    }
+
+    pub(crate) fn unregister(&self, id: u64) {
+        let mut subscribers = self.subscribers.write().unwrap();
+        for subscriber in subscribers.iter().filter(|s| s.id == id) {
+            subscriber.topics.values().for_each(|queue| queue.close());
+        }
+        subscribers.retain(|s| s.id != id);
+    }
+
+    /// 订阅主题, 返回新的队列, 匹配的主题保留的数据会立即放入队列
+    pub(crate) fn subscribe(&self, id: u64, uri: &str, policy: TopicPolicy) -> Arc<TopicQueue> {
+        let queue = Arc::new(TopicQueue::new(policy, self.stopped.clone()));
+        let mut subscribers = self.subscribers.write().unwrap();
+        if let Some(subscriber) = subscribers.iter_mut().find(|s| s.id == id) {
+            let retained = self.retained.lock().unwrap();
+            let mut list = retained
+                .values()
+                .filter(|res| matches(uri, res.uri_ref()))
+                .collect::<Vec<_>>();
+            list.sort_by(|a, b| a.uri_ref().cmp(b.uri_ref()));
+            for res in list {
+                queue.push(res.clone(), false);
+            }
+            if let Some(old) = subscriber.topics.insert(uri.to_string(), queue.clone()) {
+                old.close();
+            }
+        }
+        queue
+    }
+
+    /// 取消订阅, 指定 queue 时只有订阅的队列仍是它才取消
+    pub(crate) fn unsubscribe(&self, id: u64, uri: &str, queue: Option<&Arc<TopicQueue>>) {
+        let mut subscribers = self.subscribers.write().unwrap();
+        if let Some(subscriber) = subscribers.iter_mut().find(|s| s.id == id) {
+            let current = subscriber.topics.get(uri);
+            if current.is_some_and(|c| queue.is_none_or(|q| Arc::ptr_eq(c, q))) {
+                if let Some(queue) = subscriber.topics.remove(uri) {
+                    queue.close();
+                }
+            }
+        }
+    }
+
+    fn publish(&self, res: Response, retain: bool) {
+        // 持有 subscribers 的锁时更新, 新的订阅者不会错过或重复收到这条数据
+        // 放入队列时不持有锁, TopicPolicy::Block 阻塞时不影响订阅和取消订阅
+        let queues = {
+            let subscribers = self.subscribers.read().unwrap();
+            if retain {
+                self.retained
+                    .lock()
+                    .unwrap()
+                    .insert(res.uri_ref().to_string(), res.clone());
+            }
+            subscribers
+                .iter()
+                .flat_map(|s| s.topics.iter())
+                .filter(|(pattern, _)| matches(pattern, res.uri_ref()))
+                .map(|(_, queue)| queue.clone())
+                .collect::<Vec<_>>()
+        };
+        for queue in queues {
+            queue.push(res.clone(), true);
+        }
+    }
+}
+
+/// 主题是否与订阅的模式匹配, `+` 匹配一级, `#` 放在最后匹配剩余的任意级
+pub(crate) fn matches(pattern: &str, uri: &str) -> bool {
+    let mut patterns = pattern.split('/');
+    let mut segments = uri.split('/');
+    loop {
+        match (patterns.next(), segments.next()) {
+            (Some("#"), _) if patterns.clone().next().is_none() => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(p), Some(s)) if p == s => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("/sensor/temp", "/sensor/temp"));
+        assert!(!matches("/sensor/temp", "/sensor/temp/1"));
+        assert!(matches("/sensor/+/temp", "/sensor/1/temp"));
+        assert!(!matches("/sensor/+/temp", "/sensor/temp"));
+        assert!(!matches("/sensor/+/temp", "/sensor/1/2/temp"));
+        assert!(matches("/sensor/#", "/sensor"));
+        assert!(matches("/sensor/#", "/sensor/1/temp"));
+        assert!(!matches("/sensor/#", "/sensors/1"));
+        assert!(matches("#", "/sensor/1"));
+        assert!(!matches("/sensor/#/temp", "/sensor/1/temp"));
+    }
+}
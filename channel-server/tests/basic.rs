@@ -1,6 +1,18 @@
-use std::{thread::current, time::Instant};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::current,
+    time::{Duration, Instant},
+};
 
-use channel_server::{prelude::*, Response};
+use channel_server::{
+    builder::{Capacity, OverflowPolicy, ResponsePolicy, TopicPolicy},
+    prelude::*,
+    timeout::Timeout,
+    ChannelClient, Response, StatusCode,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct User {
@@ -14,6 +26,67 @@ fn hello(name: String) -> String {
     res
 }
 
+#[handler]
+fn slow() -> &'static str {
+    std::thread::sleep(Duration::from_millis(200));
+    "slow"
+}
+
+#[handler]
+fn upgrade(progress: Progress) -> &'static str {
+    progress.update(0.5, "写入中");
+    std::thread::sleep(Duration::from_millis(200));
+    // handler 之外的线程在结束之后报告的进度会被忽略
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        progress.update(0.9, "late");
+    });
+    "done"
+}
+
+#[handler]
+fn steps(progress: Progress) -> &'static str {
+    progress.update(0.3, "1");
+    progress.update(0.6, "2");
+    "done"
+}
+
+#[handler]
+fn scan(cancel: CancelToken) -> Result<&'static str, ChannelError> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        cancel.check()?;
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    Ok("finished")
+}
+
+#[handler]
+fn watch(cancel: CancelToken, stopped: Data<&Arc<AtomicUsize>>) -> Result<(), ChannelError> {
+    while !cancel.is_cancelled() {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    stopped.fetch_add(1, Ordering::SeqCst);
+    Err(ChannelError::Cancelled)
+}
+
+#[handler]
+async fn hello_async(name: String) -> String {
+    std::future::ready(format!("hello async: {}", name)).await
+}
+
+#[cfg(feature = "tokio")]
+#[handler]
+async fn sleep_async() -> String {
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    current().name().unwrap_or_default().to_string()
+}
+
+#[handler]
+fn boom() -> String {
+    panic!("device not found")
+}
+
 #[handler]
 fn hello_json(user: ReqParam<User>, json: Json<String>, data: Data<&i32>) -> String {
     let res = format!("user: {:?}, json: {}, data: {}", user, json.0, data.0);
@@ -29,7 +102,7 @@ fn test_basic() -> Result<(), ChannelError> {
 
     let ep = Route::new().at(uri1, hello).at(uri2, hello_json).data(1);
 
-    let (mut client, topic) = ChannelService::start(ep);
+    let (mut client, topic, _handle) = ChannelService::start(ep);
 
     println!("执行开始");
     let start_time = Instant::now();
@@ -61,20 +134,20 @@ fn test_basic() -> Result<(), ChannelError> {
         // 运行队列, 接收 response
         if client.run_once() {
             // 查询执行结果
-            if let Some(res) = client.fetch(&uri1) {
+            if let Some(res) = client.fetch(uri1) {
                 println!("{:?}", res);
                 // 如果执行成功, 清除响应
                 if res.is_ok() {
-                    client.clean(&uri1);
+                    client.clean(uri1);
                     res1_ok = true;
                 }
             }
 
-            if let Some(res) = client.fetch(&uri2) {
+            if let Some(res) = client.fetch(uri2) {
                 println!("{:?}", res);
                 // 如果执行成功, 清除响应
                 if res.is_ok() {
-                    client.clean(&uri2);
+                    client.clean(uri2);
                     res2_ok = true;
                 }
             }
@@ -98,3 +171,721 @@ fn test_basic() -> Result<(), ChannelError> {
 
     Ok(())
 }
+
+#[test]
+fn test_same_uri_in_flight() -> Result<(), ChannelError> {
+    let uri = "/hello";
+    let ep = Route::new().at(uri, hello);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+
+    // 同一个 uri 可以同时发起多个请求, 通过 id 区分结果
+    let id1 = client.req_with_body(uri, Body::from_string("first".to_string()))?;
+    let id2 = client.req_with_body(uri, Body::from_string("second".to_string()))?;
+    assert_ne!(id1, id2);
+
+    let mut bodies = Vec::new();
+    while bodies.len() < 2 {
+        client.run_once();
+        for id in [id1, id2] {
+            if let Some(res) = client.fetch_by_id(id) {
+                if res.is_ok() {
+                    let mut res = res.clone();
+                    client.clean_by_id(id);
+                    bodies.push((id, res.take_body().take()?));
+                }
+            }
+        }
+        std::thread::yield_now();
+    }
+    bodies.sort_by_key(|(id, _)| *id);
+    assert_eq!(bodies[0].1, "hello: first");
+    assert_eq!(bodies[1].1, "hello: second");
+    assert!(client.fetch(uri).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_call() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/hello", hello).at("/slow", slow);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+
+    // 等待期间收到的其它响应不受影响
+    let id = client.req_with_body("/hello", Body::from_string("queued".to_string()))?;
+
+    let mut res = client.call(
+        Request::with_body("/hello".to_string(), Body::from_string("call".to_string())),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+    assert_eq!(res.take_body().take()?, "hello: call");
+
+    let err = client
+        .call(
+            Request::with_param("/slow".to_string(), Param::empty()),
+            Duration::from_millis(10),
+        )
+        .unwrap_err();
+    assert!(matches!(err, ChannelError::Timeout));
+
+    while !client.fetch_by_id(id).is_some_and(|res| res.is_ok()) {
+        client.run_once();
+        std::thread::yield_now();
+    }
+
+    Ok(())
+}
+
+#[handler]
+fn thread_name() -> String {
+    current().name().unwrap_or_default().to_string()
+}
+
+#[test]
+fn test_worker_pool() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/thread_name", thread_name);
+    let (mut client, _topic, _handle) = ChannelService::start_with_workers(ep, 2);
+    assert_eq!(client.metrics().workers, 2);
+
+    let ids = (0..10)
+        .map(|_| client.req_with_param("/thread_name", Param::empty()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for id in ids {
+        while !client.fetch_by_id(id).is_some_and(|res| res.is_ok()) {
+            client.run_once();
+            std::thread::yield_now();
+        }
+        let mut res = client.fetch_by_id(id).unwrap().clone();
+        let name = String::from_utf8(res.take_body().take()?.to_vec())?;
+        assert!(name.starts_with("channel-server-worker-"));
+    }
+
+    while client.metrics().completed < 10 {
+        std::thread::yield_now();
+    }
+    let metrics = client.metrics();
+    assert_eq!(metrics.queue_depth, 0);
+    assert_eq!(metrics.queue_capacity, Some(100));
+
+    Ok(())
+}
+
+#[test]
+fn test_builder() -> Result<(), ChannelError> {
+    let ep = Route::new()
+        .at("/slow", slow)
+        .at("/thread_name", thread_name);
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .request_capacity(Capacity::Bounded(1))
+        .response_capacity(Capacity::Unbounded)
+        .overflow(OverflowPolicy::Reject)
+        .workers(1)
+        .thread_name("motor")
+        .stack_size(512 * 1024)
+        .start(ep);
+
+    let metrics = client.metrics();
+    assert_eq!(metrics.workers, 1);
+    assert_eq!(metrics.queue_capacity, Some(1));
+
+    // 唯一的工作线程被占用, 队列只能再放一个请求
+    client.req_with_param("/slow", Param::empty())?;
+    while client.metrics().active == 0 {
+        std::thread::yield_now();
+    }
+    let id = client.req_with_param("/thread_name", Param::empty())?;
+    let err = client
+        .req_with_param("/thread_name", Param::empty())
+        .unwrap_err();
+    assert!(matches!(err, ChannelError::QueueFull));
+    assert_eq!(client.fetch_all("/thread_name").count(), 1);
+
+    while !client.fetch_by_id(id).is_some_and(|res| res.is_ok()) {
+        client.run_once();
+        std::thread::yield_now();
+    }
+    let mut res = client.fetch_by_id(id).unwrap().clone();
+    assert_eq!(res.take_body().take()?, "motor-0");
+
+    Ok(())
+}
+
+#[test]
+fn test_response_policy() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/hello", hello);
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .response_capacity(Capacity::Bounded(1))
+        .response_policy(ResponsePolicy::DropOldest)
+        .start(ep.clone());
+
+    let ids = (0..3)
+        .map(|_| client.req_with_body("/hello", Body::from_string("drop".to_string())))
+        .collect::<Result<Vec<_>, _>>()?;
+    while client.metrics().completed < 3 {
+        std::thread::yield_now();
+    }
+    client.run_once();
+
+    // 只有最后一个响应留在队列中, 其余的被丢弃
+    let dropped = ChannelError::ResponseDropped.to_string();
+    let statuses = ids
+        .iter()
+        .map(|id| client.fetch_by_id(*id).unwrap().status_ref().clone())
+        .collect::<Vec<_>>();
+    assert_eq!(statuses.iter().filter(|status| status.is_ok()).count(), 1);
+    assert_eq!(
+        statuses
+            .iter()
+            .filter(|status| matches!(status, StatusCode::Fail(msg) if msg == &dropped))
+            .count(),
+        2
+    );
+
+    let dead_letters = Arc::new(AtomicUsize::new(0));
+    let counter = dead_letters.clone();
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .response_capacity(Capacity::Bounded(1))
+        .response_policy(ResponsePolicy::dead_letter(move |_res| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }))
+        .start(ep);
+
+    for _ in 0..3 {
+        client.req_with_body("/hello", Body::from_string("dead".to_string()))?;
+    }
+    // 全部处理完之前不能取走响应, 否则队列不会满
+    while client.metrics().completed < 3 {
+        std::thread::yield_now();
+    }
+    client.run_once();
+    assert!(client
+        .fetch_all("/hello")
+        .all(|res| !matches!(res.status_ref(), StatusCode::Ready(_))));
+    assert_eq!(dead_letters.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_flood_before_polling() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/hello", hello);
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .workers(1)
+        .request_capacity(Capacity::Bounded(2))
+        .response_capacity(Capacity::Bounded(2))
+        .start(ep);
+
+    // 默认的策略下, 发起超过队列容量的请求之后再取响应, 不会互相等待
+    let ids = (0..20)
+        .map(|_| client.req_with_body("/hello", Body::from_string("flood".to_string())))
+        .collect::<Result<Vec<_>, _>>()?;
+    while client.metrics().completed < 20 {
+        std::thread::yield_now();
+    }
+    client.run_once();
+    let statuses = ids
+        .iter()
+        .map(|id| client.fetch_by_id(*id).unwrap().status_ref().clone())
+        .collect::<Vec<_>>();
+    assert!(statuses.iter().all(|status| status.is_finished()));
+    assert_eq!(statuses.iter().filter(|status| status.is_ok()).count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_stalled_client() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/hello", hello);
+    let (mut stalled, _topic, handle) = ChannelService::builder()
+        .workers(2)
+        .response_capacity(Capacity::Bounded(1))
+        .response_policy(ResponsePolicy::Block(Duration::from_millis(50)))
+        .start(ep);
+    let mut other = handle.new_client();
+
+    // stalled 一直不取响应, 工作线程为它最多等待 50ms, 不会影响其它 client
+    for _ in 0..10 {
+        stalled.req_with_body("/hello", Body::from_string("stalled".to_string()))?;
+    }
+    let res = other.call(
+        Request::with_body("/hello".to_string(), Body::from_string("other".to_string())),
+        Duration::from_secs(5),
+    )?;
+    assert!(res.is_ok());
+
+    // 等待超时的响应被丢弃
+    while stalled.metrics().completed < 11 {
+        std::thread::yield_now();
+    }
+    stalled.run_once();
+    let statuses = stalled
+        .fetch_all("/hello")
+        .map(|res| res.status_ref().clone())
+        .collect::<Vec<_>>();
+    assert_eq!(statuses.len(), 10);
+    assert!(statuses.iter().all(|status| status.is_finished()));
+    assert_eq!(statuses.iter().filter(|status| status.is_ok()).count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_shutdown() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/slow", slow);
+    let (mut client, _topic, handle) = ChannelService::start_with_workers(ep.clone(), 1);
+
+    // 正在执行和队列中的请求在停止前都会处理完
+    let id1 = client.req_with_param("/slow", Param::empty())?;
+    let id2 = client.req_with_param("/slow", Param::empty())?;
+    assert!(handle.is_running());
+    handle.shutdown(Duration::from_secs(5))?;
+
+    let err = client.req_with_param("/slow", Param::empty()).unwrap_err();
+    assert!(matches!(err, ChannelError::ServiceStopped));
+
+    client.run_once();
+    assert!(client.fetch_by_id(id1).unwrap().is_ok());
+    assert!(client.fetch_by_id(id2).unwrap().is_ok());
+
+    // 超时后不再等待工作线程
+    let (mut client, _topic, handle) = ChannelService::start_with_workers(ep, 1);
+    client.req_with_param("/slow", Param::empty())?;
+    let err = handle.shutdown(Duration::from_millis(10)).unwrap_err();
+    assert!(matches!(err, ChannelError::Timeout));
+
+    // 不使用 ServiceHandle 时, drop 它不会停止服务
+    let (mut client, _, _) = ChannelService::start(Route::new().at("/hello", hello));
+    let res = client.call(
+        Request::with_body("/hello".to_string(), Body::from_string("alive".to_string())),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+
+    // 为不取数据的 client 阻塞的工作线程和发布者, 在停止时放弃等待
+    let (mut client, topic, handle) = ChannelService::builder()
+        .workers(1)
+        .response_capacity(Capacity::Bounded(1))
+        .response_policy(ResponsePolicy::Block(Duration::from_secs(60)))
+        .start(Route::new().at("/hello", hello));
+    let _subscription = client.subscribe_with("/block", TopicPolicy::Block(1));
+    let publisher = std::thread::spawn(move || {
+        for _ in 0..3 {
+            topic.publish(Response::topic("/block"));
+        }
+    });
+    let ids = (0..3)
+        .map(|_| client.req_with_body("/hello", Body::from_string("block".to_string())))
+        .collect::<Result<Vec<_>, _>>()?;
+    while client.metrics().completed == 0 || client.metrics().active == 0 {
+        std::thread::yield_now();
+    }
+    handle.shutdown(Duration::from_secs(5))?;
+    publisher.join().unwrap();
+
+    client.run_once();
+    let statuses = ids
+        .iter()
+        .map(|id| client.fetch_by_id(*id).unwrap().status_ref().clone())
+        .collect::<Vec<_>>();
+    assert!(statuses[0].is_ok());
+    assert!(statuses[1..].iter().all(|status| !status.is_ok()));
+
+    Ok(())
+}
+
+#[test]
+fn test_handler_panic() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/boom", boom).at("/hello", hello);
+    let (mut client, _topic, _handle) = ChannelService::start_with_workers(ep, 1);
+
+    let res = client.call(
+        Request::with_param("/boom".to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )?;
+    let expected = ChannelError::HandlerPanicked("device not found".into()).to_string();
+    assert!(matches!(res.status_ref(), StatusCode::Fail(msg) if msg == &expected));
+    assert_eq!(res.uri_ref(), "/boom");
+
+    // 工作线程没有因为 panic 退出
+    let res = client.call(
+        Request::with_body("/hello".to_string(), Body::from_string("again".to_string())),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_multiple_clients() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/hello", hello);
+    let (mut client, _topic, handle) = ChannelService::start(ep);
+    let service = handle.service();
+
+    // 每个 client 在自己的线程中发起请求, 只会收到自己的响应
+    let threads = (0..4)
+        .map(|i| {
+            let service = service.clone();
+            std::thread::spawn(move || -> Result<(), ChannelError> {
+                let mut client = service.new_client();
+                for j in 0..10 {
+                    let name = format!("{}-{}", i, j);
+                    let mut res = client.call(
+                        Request::with_body("/hello".to_string(), Body::from_string(name.clone())),
+                        Duration::from_secs(1),
+                    )?;
+                    assert_eq!(res.take_body().take()?, format!("hello: {}", name));
+                }
+                Ok(())
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap()?;
+    }
+
+    // 其它 client 的响应不会出现在这里
+    let mut other = handle.new_client();
+    let id = client.req_with_body("/hello", Body::from_string("main".to_string()))?;
+    while !client.fetch_by_id(id).is_some_and(|res| res.is_ok()) {
+        client.run_once();
+        std::thread::yield_now();
+    }
+    assert!(!other.run_once());
+    assert!(other.fetch("/hello").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_progress() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/upgrade", upgrade);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+
+    let id = client.req_with_param("/upgrade", Param::empty())?;
+    loop {
+        client.run_once();
+        let res = client.fetch_by_id(id).unwrap();
+        if res.status_ref().is_pending() {
+            assert!(matches!(res.status_ref(), StatusCode::Pending(msg) if msg == "写入中"));
+            assert_eq!(res.progress_ref(), Some(0.5));
+            break;
+        }
+        std::thread::yield_now();
+    }
+    while !client.fetch_by_id(id).unwrap().is_ok() {
+        client.run_once();
+        std::thread::yield_now();
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    client.run_once();
+    let res = client.fetch_by_id(id).unwrap();
+    assert!(res.is_ok());
+    assert_eq!(res.progress_ref(), None);
+
+    // call 忽略进度, 只返回最终的结果
+    let mut res = client.call(
+        Request::with_param("/upgrade".to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )?;
+    assert_eq!(res.take_body().take()?, "done");
+
+    // 响应队列满时被挤掉的只是进度, 不影响最终的结果
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .response_capacity(Capacity::Bounded(1))
+        .response_policy(ResponsePolicy::DropOldest)
+        .start(Route::new().at("/steps", steps));
+    let id = client.req_with_param("/steps", Param::empty())?;
+    while client.metrics().completed < 1 {
+        std::thread::yield_now();
+    }
+    client.run_once();
+    assert!(client.fetch_by_id(id).unwrap().is_ok());
+    let res = client.call(
+        Request::with_param("/steps".to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_cancel() -> Result<(), ChannelError> {
+    let ep = Route::new()
+        .at("/scan", scan)
+        .at("/hello", hello)
+        .at("/slow", slow);
+    let (mut client, _topic, _handle) = ChannelService::start_with_workers(ep, 1);
+
+    let scan_id = client.req_with_param("/scan", Param::empty())?;
+    let hello_id = client.req_with_body("/hello", Body::from_string("queued".to_string()))?;
+    while client.metrics().active == 0 {
+        std::thread::yield_now();
+    }
+
+    // 正在执行和还在队列中的请求都可以取消
+    assert!(client.cancel(hello_id));
+    assert!(client.cancel(scan_id));
+    for id in [scan_id, hello_id] {
+        while !client.fetch_by_id(id).unwrap().status_ref().is_finished() {
+            client.run_once();
+            std::thread::yield_now();
+        }
+        assert!(client.fetch_by_id(id).unwrap().status_ref().is_cancelled());
+    }
+    assert!(!client.cancel(scan_id));
+
+    // call 超时后请求被取消, 工作线程可以处理之后的请求
+    let err = client
+        .call(
+            Request::with_param("/scan".to_string(), Param::empty()),
+            Duration::from_millis(20),
+        )
+        .unwrap_err();
+    assert!(matches!(err, ChannelError::Timeout));
+    let res = client.call(
+        Request::with_body("/hello".to_string(), Body::from_string("after".to_string())),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+
+    // handler 不理会取消, 执行完成后保留它的结果
+    let id = client.req_with_param("/slow", Param::empty())?;
+    while client.metrics().active == 0 {
+        std::thread::yield_now();
+    }
+    assert!(client.cancel(id));
+    while !client.fetch_by_id(id).unwrap().status_ref().is_finished() {
+        client.run_once();
+        std::thread::yield_now();
+    }
+    assert!(client.fetch_by_id(id).unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_timeout() -> Result<(), ChannelError> {
+    let stopped = Arc::new(AtomicUsize::new(0));
+    let ep = Route::new()
+        .at(
+            "/watch",
+            watch.with(Timeout::new(Duration::from_millis(50))),
+        )
+        .at(
+            "/watch_long",
+            watch.with(Timeout::new(Duration::from_secs(5))),
+        )
+        .at("/hello", hello.with(Timeout::new(Duration::from_secs(1))))
+        .data(stopped.clone());
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+
+    let res = client.call(
+        Request::with_body("/hello".to_string(), Body::from_string("fast".to_string())),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+
+    // 超时后请求失败, handler 通过 CancelToken 得知后结束
+    let res = client.call(
+        Request::with_param("/watch".to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )?;
+    let expected = ChannelError::Timeout.to_string();
+    assert!(matches!(res.status_ref(), StatusCode::Fail(msg) if msg == &expected));
+    while stopped.load(Ordering::SeqCst) == 0 {
+        std::thread::yield_now();
+    }
+
+    // client 取消时 handler 同样会结束, 状态为已取消
+    let id = client.req_with_param("/watch_long", Param::empty())?;
+    while client.metrics().active == 0 {
+        std::thread::yield_now();
+    }
+    client.cancel(id);
+    while !client.fetch_by_id(id).unwrap().status_ref().is_finished() {
+        client.run_once();
+        std::thread::yield_now();
+    }
+    assert!(client.fetch_by_id(id).unwrap().status_ref().is_cancelled());
+    while stopped.load(Ordering::SeqCst) < 2 {
+        std::thread::yield_now();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_timeout_threads() -> Result<(), ChannelError> {
+    let ep = Route::new()
+        .at(
+            "/thread_name",
+            thread_name.with(Timeout::new(Duration::from_secs(1))),
+        )
+        .at(
+            "/slow",
+            slow.with(Timeout::new(Duration::from_millis(20)).max_timed_out(1)),
+        );
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .workers(1)
+        .thread_name("motor")
+        .start(ep);
+    let call = |client: &mut ChannelClient, uri: &str| {
+        client.call(
+            Request::with_param(uri.to_string(), Param::empty()),
+            Duration::from_secs(1),
+        )
+    };
+
+    let mut res = call(&mut client, "/thread_name")?;
+    assert_eq!(res.take_body().take()?, "motor-0-timeout");
+
+    // 超时的 handler 仍占用线程, 达到上限后新的请求直接失败
+    let failed = |res: Response, err: ChannelError| {
+        let expected = err.to_string();
+        matches!(res.status_ref(), StatusCode::Fail(msg) if msg == &expected)
+    };
+    assert!(failed(call(&mut client, "/slow")?, ChannelError::Timeout));
+    assert!(failed(
+        call(&mut client, "/slow")?,
+        ChannelError::TooManyTimedOut
+    ));
+    std::thread::sleep(Duration::from_millis(400));
+    assert!(failed(call(&mut client, "/slow")?, ChannelError::Timeout));
+
+    Ok(())
+}
+
+#[test]
+fn test_async_handler() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/hello", hello_async);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+
+    let mut res = client.call(
+        Request::with_body("/hello".to_string(), Body::from_string("maxu".to_string())),
+        Duration::from_secs(1),
+    )?;
+    assert_eq!(res.take_body().take()?, "hello async: maxu");
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_tokio_runtime() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/sleep", sleep_async);
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .thread_name("motor")
+        .start(ep.clone());
+    let mut res = client.call(
+        Request::with_param("/sleep".to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.take_body().take()?.starts_with(b"motor-"));
+
+    // 使用已有的 runtime
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (mut client, _topic, _handle) = ChannelService::builder()
+        .runtime(runtime.handle().clone())
+        .start(ep);
+    let res = client.call(
+        Request::with_param("/sleep".to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+
+    // 在 async 代码中直接调用 Endpoint, 不会使用当前的 runtime
+    use channel_server::Endpoint;
+    let mut res = runtime.block_on(async {
+        hello_async.call(Request::with_body(
+            "/hello".to_string(),
+            Body::from_string("direct".to_string()),
+        ))
+    })?;
+    assert_eq!(res.take_body().take()?, "hello async: direct");
+
+    // current_thread runtime 无法在工作线程中驱动定时器
+    let current = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let handle = current.handle().clone();
+    assert!(std::panic::catch_unwind(|| ChannelService::builder().runtime(handle)).is_err());
+
+    Ok(())
+}
+
+/// 不依赖 runtime 的简单执行器, 验证 ResponseFuture 通过 Waker 唤醒
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut fut = std::pin::pin!(fut);
+    let waker = Arc::new(ThreadWaker(current())).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(output) => return output,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+fn test_send_future() -> Result<(), ChannelError> {
+    let ep = Route::new()
+        .at("/hello", hello)
+        .at("/upgrade", upgrade)
+        .at("/scan", scan);
+    let (mut client, _topic, handle) = ChannelService::start_with_workers(ep, 2);
+
+    let fut = client.send(Request::with_body(
+        "/hello".to_string(),
+        Body::from_string("future".to_string()),
+    ));
+    let mut res = block_on(fut)?;
+    assert_eq!(res.take_body().take()?, "hello: future");
+
+    // 只在请求结束时完成, 不经过响应队列
+    let fut = client.send(Request::with_param("/upgrade".to_string(), Param::empty()));
+    let res = block_on(fut)?;
+    assert!(res.is_ok());
+    assert!(!client.run_once());
+
+    let fut = client.send(Request::with_param("/scan".to_string(), Param::empty()));
+    fut.cancel();
+    assert!(block_on(fut)?.status_ref().is_cancelled());
+
+    handle.shutdown(Duration::from_secs(5))?;
+    let fut = client.send(Request::with_param("/hello".to_string(), Param::empty()));
+    assert!(matches!(block_on(fut), Err(ChannelError::ServiceStopped)));
+
+    // 请求队列满时不阻塞, 直接失败
+    let ep = Route::new().at("/hello", hello).at("/scan", scan);
+    let (client, _topic, _handle) = ChannelService::builder()
+        .workers(1)
+        .request_capacity(Capacity::Bounded(1))
+        .start(ep);
+    let running = client.send(Request::with_param("/scan".to_string(), Param::empty()));
+    while client.metrics().active == 0 {
+        std::thread::yield_now();
+    }
+    let queued = client.send(Request::with_body(
+        "/hello".to_string(),
+        Body::from_string("queued".to_string()),
+    ));
+    let full = client.send(Request::with_param("/hello".to_string(), Param::empty()));
+    assert!(matches!(block_on(full), Err(ChannelError::QueueFull)));
+
+    // drop 时取消请求, 工作线程可以处理之后的请求
+    drop(running);
+    assert!(block_on(queued)?.is_ok());
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use channel_server::{prelude::*, ChannelClient, Endpoint, Response};
+
+#[derive(Debug, Deserialize)]
+struct Motor {
+    id: u8,
+    name: String,
+}
+
+/// 读取电机的速度
+#[handler]
+fn speed(Path(id): Path<u8>) -> String {
+    format!("motor {} speed", id)
+}
+
+#[handler]
+fn motor(Path(motor): Path<Motor>) -> String {
+    format!("motor {}: {}", motor.id, motor.name)
+}
+
+#[handler]
+fn position(Path((id, axis)): Path<(u8, String)>) -> String {
+    format!("motor {} axis {}", id, axis)
+}
+
+#[handler]
+fn files(Path(path): Path<String>) -> String {
+    format!("file {}", path)
+}
+
+fn call(client: &mut ChannelClient, uri: &str) -> Result<Response, ChannelError> {
+    client.call(
+        Request::with_param(uri.to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )
+}
+
+fn body(mut res: Response) -> String {
+    assert!(res.is_ok(), "{:?}", res);
+    String::from_utf8(res.take_body().take().unwrap().to_vec()).unwrap()
+}
+
+#[test]
+fn test_path_params() -> Result<(), ChannelError> {
+    let ep = Route::new()
+        .at("/motor/:id/speed", speed)
+        .at("/motor/:id/name/:name", motor)
+        .at("/motor/:id/position/:axis", position)
+        .at("/files/*path", files);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+
+    assert_eq!(body(call(&mut client, "/motor/3/speed")?), "motor 3 speed");
+    assert_eq!(
+        body(call(&mut client, "/motor/1/name/left")?),
+        "motor 1: left"
+    );
+    assert_eq!(
+        body(call(&mut client, "/motor/2/position/x")?),
+        "motor 2 axis x"
+    );
+    assert_eq!(body(call(&mut client, "/files/a/b.txt")?), "file a/b.txt");
+
+    // 参数类型不匹配
+    let res = call(&mut client, "/motor/abc/speed")?;
+    assert!(!res.is_ok());
+
+    let res = call(&mut client, "/motor/3")?;
+    assert!(!res.is_ok());
+
+    Ok(())
+}
+
+/// 返回 handler 看到的 uri
+struct Uri;
+
+impl Endpoint for Uri {
+    type Output = Response;
+
+    fn call(&self, req: Request) -> Result<Self::Output, ChannelError> {
+        Ok(req.uri_ref().to_string().into_response())
+    }
+}
+
+#[test]
+fn test_nest() -> Result<(), ChannelError> {
+    let axis = Route::new().at("/:axis", position);
+    let motors = Route::new()
+        .at("/", Uri)
+        .at("/:id/speed", speed)
+        .at("/:id/uri", Uri)
+        .nest("/:id/position", axis);
+    let ep = Route::new().nest("/motor", motors);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+
+    assert_eq!(body(call(&mut client, "/motor")?), "/");
+    assert_eq!(body(call(&mut client, "/motor/3/speed")?), "motor 3 speed");
+    assert_eq!(body(call(&mut client, "/motor/3/uri")?), "/3/uri");
+    assert_eq!(
+        body(call(&mut client, "/motor/2/position/y")?),
+        "motor 2 axis y"
+    );
+
+    // 响应中仍然是完整的 uri
+    let res = call(&mut client, "/motor/3/speed")?;
+    assert_eq!(res.uri_ref(), "/motor/3/speed");
+
+    assert!(!call(&mut client, "/motor/3/unknown")?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_runtime_routes() -> Result<(), ChannelError> {
+    let route = Route::new().at("/motor/:id/speed", speed);
+    let (mut client, _topic, _handle) = ChannelService::start(route.clone());
+
+    assert!(!call(&mut client, "/files/a")?.is_ok());
+
+    // 服务启动后注册的路径立即生效
+    let plugin = String::from("/files/*path");
+    route.insert(plugin.clone(), files)?;
+    assert_eq!(body(call(&mut client, "/files/a")?), "file a");
+    assert!(matches!(
+        route.insert(plugin.clone(), files),
+        Err(ChannelError::InvalidPath(_))
+    ));
+
+    route.replace("/motor/:id/speed", position)?;
+    assert!(!call(&mut client, "/motor/3/speed")?.is_ok());
+    route.replace("/motor/:id/speed", speed)?;
+    assert_eq!(body(call(&mut client, "/motor/3/speed")?), "motor 3 speed");
+
+    route.remove(&plugin)?;
+    assert!(!call(&mut client, "/files/a")?.is_ok());
+    assert!(matches!(
+        route.remove(&plugin),
+        Err(ChannelError::PathNotFoundError(_))
+    ));
+
+    route.insert_nest("/plugin", Route::new().at("/files/*path", files))?;
+    assert_eq!(body(call(&mut client, "/plugin/files/b")?), "file b");
+    route.remove("/plugin")?;
+    assert!(!call(&mut client, "/plugin/files/b")?.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_fallback() -> Result<(), ChannelError> {
+    // 兼容改名前的 uri
+    let compat = Route::new().at("/motor_speed/:id", speed);
+    let ep = Route::new().at("/motor/:id/speed", speed).fallback(compat);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+    assert_eq!(body(call(&mut client, "/motor_speed/1")?), "motor 1 speed");
+    assert!(!call(&mut client, "/unknown")?.is_ok());
+
+    let ep = Route::new()
+        .at("/motor/:id/speed", speed)
+        .not_found(|req| format!("unknown: {}", req.uri_ref()).into_response());
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+    assert_eq!(body(call(&mut client, "/unknown")?), "unknown: /unknown");
+
+    Ok(())
+}
+
+#[test]
+fn test_paths() -> Result<(), ChannelError> {
+    let motors = Route::new()
+        .at("/", files)
+        .at("/:id/speed", speed)
+        .at("/:id/name/:name", motor);
+    let ep = Route::new()
+        .nest("/motor", motors)
+        .at("/files/*path", files)
+        .paths_endpoint("/_routes");
+
+    let paths = ep.paths();
+    let names: Vec<_> = paths.iter().map(|p| p.path.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "/_routes",
+            "/files/*path",
+            "/motor",
+            "/motor/:id/name/:name",
+            "/motor/:id/speed"
+        ]
+    );
+    let info = &paths[4].info;
+    assert_eq!(info.description.as_deref(), Some("读取电机的速度"));
+    assert_eq!(info.params, ["Path<u8>"]);
+    assert_eq!(info.response, Some("String"));
+    assert_eq!(paths[3].info.description, None);
+
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+    let routes: serde_json::Value =
+        serde_json::from_str(&body(call(&mut client, "/_routes")?)).unwrap();
+    assert_eq!(routes.as_array().unwrap().len(), 5);
+    assert_eq!(routes[4]["path"], "/motor/:id/speed");
+    assert_eq!(routes[4]["description"], "读取电机的速度");
+    assert_eq!(routes[4]["params"][0], "Path<u8>");
+    Ok(())
+}
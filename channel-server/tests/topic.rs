@@ -0,0 +1,262 @@
+use std::{thread, time::Duration};
+
+use channel_server::{
+    builder::{Capacity, TopicPolicy},
+    prelude::*,
+    ChannelClient, ChannelTopic, Response, Topic,
+};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Temperature {
+    sensor: u8,
+    value: f32,
+}
+
+const TEMPERATURE: Topic<Temperature> = Topic::new("/sensor/temp");
+
+#[handler]
+fn measure(publisher: Publisher) -> &'static str {
+    for sensor in 0..3 {
+        let temp = Temperature {
+            sensor,
+            value: 20.0,
+        };
+        publisher.publish_typed(&TEMPERATURE, &temp).unwrap();
+    }
+    "done"
+}
+
+fn topic_bodies(client: &mut ChannelClient, uri: &'static str) -> Vec<String> {
+    client.run_once();
+    client
+        .fetch_topic(uri)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|mut res| String::from_utf8(res.take_body().take().unwrap().to_vec()).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_broadcast() -> Result<(), ChannelError> {
+    let uri = "/topic/video";
+    let (mut client, topic, handle) = ChannelService::builder()
+        .topic_capacity(Capacity::Bounded(2))
+        .start(Route::new());
+    let mut fast = handle.new_client();
+    let mut other = handle.new_client();
+    client.subject(uri);
+    fast.subject(uri);
+
+    // 每个订阅者都收到一份
+    topic.publish(Response::topic(uri).body("first".as_bytes().into()));
+    assert_eq!(topic_bodies(&mut client, uri), ["first"]);
+    assert_eq!(topic_bodies(&mut fast, uri), ["first"]);
+    assert!(!other.run_once());
+
+    // client 不及时取数据, 不会阻塞发布者, 也不影响其它订阅者
+    for i in 0..5 {
+        topic.publish(Response::topic(uri).body(format!("{}", i).into_bytes().into()));
+        assert_eq!(topic_bodies(&mut fast, uri), [format!("{}", i)]);
+    }
+    assert_eq!(topic_bodies(&mut client, uri), ["0", "1"]);
+
+    // drop 的 client 不再接收数据
+    drop(fast);
+    topic.publish(Response::topic(uri).body("last".as_bytes().into()));
+    assert_eq!(topic_bodies(&mut client, uri), ["last"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_wildcard() -> Result<(), ChannelError> {
+    let (mut client, topic, _handle) = ChannelService::start(Route::new());
+    client.subject("/sensor/+/temp");
+    client.subject(String::from("/sensor/#"));
+
+    for uri in [
+        "/sensor/1/temp",
+        "/sensor/2/humidity",
+        "/sensor/2/temp",
+        "/motor/1",
+    ] {
+        topic.publish(Response::topic(uri).body(uri.as_bytes().into()));
+    }
+    client.run_once();
+
+    // 返回的数据保留实际的主题
+    let uris = |list: Vec<Response>| {
+        list.iter()
+            .map(|res| res.uri_ref().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        uris(client.fetch_topic("/sensor/+/temp").unwrap()),
+        ["/sensor/1/temp", "/sensor/2/temp"]
+    );
+    assert_eq!(
+        uris(client.fetch_topic("/sensor/#").unwrap()),
+        ["/sensor/1/temp", "/sensor/2/humidity", "/sensor/2/temp"]
+    );
+    assert!(client.fetch_topic("/sensor/#").unwrap().is_empty());
+    assert!(client.fetch_topic("/motor/1").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_unsubscribe() -> Result<(), ChannelError> {
+    let (mut client, topic, _handle) = ChannelService::start(Route::new());
+    let uri = "/topic/video";
+    assert!(!topic.has_subscribers(uri));
+
+    client.subject(uri);
+    assert!(topic.has_subscribers(uri));
+    topic.publish(Response::topic(uri).body("before".as_bytes().into()));
+    client.unsubscribe(uri);
+    assert!(!topic.has_subscribers(uri));
+    topic.publish(Response::topic(uri).body("after".as_bytes().into()));
+    assert!(!client.run_once());
+    assert!(client.fetch_topic(uri).is_none());
+
+    // drop 时取消订阅
+    let subscription = client.subscribe("/sensor/#");
+    assert_eq!(subscription.uri(), "/sensor/#");
+    assert!(topic.has_subscribers("/sensor/1"));
+    topic.publish(Response::topic("/sensor/1").body("1".as_bytes().into()));
+    assert_eq!(topic_bodies(&mut client, "/sensor/#"), ["1"]);
+
+    drop(subscription);
+    assert!(!topic.has_subscribers("/sensor/1"));
+    topic.publish(Response::topic("/sensor/1").body("2".as_bytes().into()));
+    assert!(!client.run_once());
+    assert!(client.fetch_topic("/sensor/#").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_retained() -> Result<(), ChannelError> {
+    let (mut client, topic, handle) = ChannelService::start(Route::new());
+    let uri = "/topic/video";
+
+    topic.publish(Response::topic(uri).body("plain".as_bytes().into()));
+    topic.publish_retained(Response::topic(uri).body("first".as_bytes().into()));
+    topic.publish_retained(Response::topic(uri).body("latest".as_bytes().into()));
+    topic.publish_retained(Response::topic("/topic/audio").body("audio".as_bytes().into()));
+
+    // 之后订阅的 client 立即收到最新的一条
+    client.subject(uri);
+    assert_eq!(topic_bodies(&mut client, uri), ["latest"]);
+    let mut other = handle.new_client();
+    other.subject("/topic/#");
+    assert_eq!(topic_bodies(&mut other, "/topic/#"), ["audio", "latest"]);
+
+    topic.clear_retained(uri);
+    let mut late = handle.new_client();
+    late.subject(uri);
+    assert!(topic_bodies(&mut late, uri).is_empty());
+
+    Ok(())
+}
+
+fn publish_numbers(topic: &ChannelTopic, uri: &str, numbers: std::ops::Range<usize>) {
+    for i in numbers {
+        topic.publish(Response::topic(uri).body(format!("{}", i).into_bytes().into()));
+    }
+}
+
+#[test]
+fn test_topic_policy() -> Result<(), ChannelError> {
+    let (mut client, topic, _handle) = ChannelService::builder()
+        .topic_policy(TopicPolicy::KeepLatest(3))
+        .start(Route::new());
+    client.subject("/latest3");
+    client.subject_with("/latest", TopicPolicy::Latest);
+    client.subject_with("/newest", TopicPolicy::DropNewest(2));
+    client.subject_with("/all", TopicPolicy::Unbounded);
+    client.subject_with("/zero", TopicPolicy::DropNewest(0));
+
+    for uri in ["/latest3", "/latest", "/newest", "/all", "/zero"] {
+        publish_numbers(&topic, uri, 0..5);
+    }
+    assert_eq!(topic_bodies(&mut client, "/latest3"), ["2", "3", "4"]);
+    assert_eq!(topic_bodies(&mut client, "/latest"), ["4"]);
+    assert_eq!(topic_bodies(&mut client, "/newest"), ["0", "1"]);
+    assert_eq!(topic_bodies(&mut client, "/all").len(), 5);
+    assert_eq!(topic_bodies(&mut client, "/zero"), ["0"]);
+
+    // 队列满时阻塞发布者, 直到 client 取走数据
+    let _subscription = client.subscribe_with("/block", TopicPolicy::Block(2));
+    let publisher = {
+        let topic = topic.clone();
+        thread::spawn(move || publish_numbers(&topic, "/block", 0..4))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!publisher.is_finished());
+    assert_eq!(topic_bodies(&mut client, "/block"), ["0", "1"]);
+    publisher.join().unwrap();
+    assert_eq!(topic_bodies(&mut client, "/block"), ["2", "3"]);
+
+    // 取消订阅时唤醒阻塞的发布者
+    let publisher = {
+        let topic = topic.clone();
+        thread::spawn(move || publish_numbers(&topic, "/block", 0..4))
+    };
+    thread::sleep(Duration::from_millis(50));
+    client.unsubscribe("/block");
+    publisher.join().unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn test_typed() -> Result<(), ChannelError> {
+    let (mut client, topic, _handle) = ChannelService::start(Route::new());
+    client.subject(TEMPERATURE.uri());
+    client.subject("/sensor/#");
+
+    let temp = Temperature {
+        sensor: 1,
+        value: 21.5,
+    };
+    topic.publish_typed(&TEMPERATURE, &temp)?;
+    topic.publish(Response::topic(TEMPERATURE.uri()).body("oops".as_bytes().into()));
+    client.run_once();
+
+    let list = client.fetch_typed(&TEMPERATURE);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].as_ref().unwrap(), &temp);
+    assert!(matches!(list[1], Err(ChannelError::ParseJsonError)));
+
+    topic.publish_json("/sensor/count", &3)?;
+    client.run_once();
+    let list = client.fetch_topic_as::<u32>("/sensor/#");
+    assert!(list[0].is_err());
+    assert_eq!(list[2].as_ref().unwrap(), &3);
+    assert!(client.fetch_topic_as::<u32>("/motor").is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_publisher() -> Result<(), ChannelError> {
+    let ep = Route::new().at("/measure", measure);
+    let (mut client, _topic, _handle) = ChannelService::start(ep);
+    client.subject(TEMPERATURE.uri());
+
+    let res = client.call(
+        Request::with_param("/measure".to_string(), Param::empty()),
+        Duration::from_secs(1),
+    )?;
+    assert!(res.is_ok());
+    client.run_once();
+    let sensors = client
+        .fetch_typed(&TEMPERATURE)
+        .into_iter()
+        .map(|temp| temp.map(|temp| temp.sensor))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(sensors, [0, 1, 2]);
+
+    Ok(())
+}